@@ -0,0 +1,104 @@
+use std::io;
+use std::io::BufRead;
+use std::net::{UdpSocket, SocketAddr};
+use std::time::Duration;
+
+use bincode::SizeLimit;
+use bincode::rustc_serialize::{encode, decode};
+
+use vp_shared::PROTOCOL_VERSION;
+use vp_shared::discovery::{DiscoveryQuery, DiscoveryResponse, DISCOVERY_PORT};
+
+const RESPONSE_TIMEOUT_MS: u64 = 1000;
+const MAX_RESPONSE_SIZE: usize = 1024;
+
+pub fn discover_servers() -> io::Result<Vec<(SocketAddr, DiscoveryResponse)>>
+{
+    let socket = try!(UdpSocket::bind("0.0.0.0:0"));
+    try!(socket.set_broadcast(true));
+    try!(socket.set_read_timeout(Some(Duration::from_millis(RESPONSE_TIMEOUT_MS))));
+
+    let query = encode(&DiscoveryQuery::new(), SizeLimit::Infinite).unwrap();
+    try!(socket.send_to(&query, ("255.255.255.255", DISCOVERY_PORT)));
+
+    let mut servers: Vec<(SocketAddr, DiscoveryResponse)> = Vec::new();
+    let mut buffer = [0; MAX_RESPONSE_SIZE];
+
+    // collect answers until no server has replied for a whole timeout
+    loop
+    {
+        let (length, sender) = match socket.recv_from(&mut buffer)
+        {
+            Ok(received) => received,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => break,
+            Err(e) => return Err(e)
+        };
+
+        let response = match decode::<DiscoveryResponse>(&buffer[..length])
+        {
+            Ok(response) => response,
+            Err(_) => continue
+        };
+
+        if !response.is_valid()
+        {
+            continue;
+        }
+
+        let address = SocketAddr::new(sender.ip(), response.game_port);
+        if !servers.iter().any(|&(known_address, _)| known_address == address)
+        {
+            servers.push((address, response));
+        }
+    }
+
+    Ok(servers)
+}
+
+pub fn choose_server() -> Option<SocketAddr>
+{
+    println!("Looking for servers on the local network...");
+
+    let servers = match discover_servers()
+    {
+        Ok(servers) => servers,
+        Err(e) =>
+        {
+            println!("Server discovery failed. {}", e);
+            return None;
+        }
+    };
+
+    if servers.len() == 0
+    {
+        println!("No servers found.");
+        return None;
+    }
+
+    for (index, &(address, ref server)) in servers.iter().enumerate()
+    {
+        let compatibility = if server.is_compatible() { String::new() } else { format!(", incompatible protocol version {}", server.protocol_version) };
+        println!("{}) {} - {} on {}, {}/{} players{}", index + 1, server.name, address, server.map, server.player_count, server.max_players, compatibility);
+    }
+
+    println!("Pick a server:");
+    let stdin = io::stdin();
+    for line in stdin.lock().lines()
+    {
+        match line.ok().and_then(|line| line.trim().parse::<usize>().ok())
+        {
+            Some(choice) if choice >= 1 && choice <= servers.len() =>
+            {
+                let (address, ref server) = servers[choice - 1];
+                if server.is_compatible()
+                {
+                    return Some(address);
+                }
+                println!("{} runs protocol version {}, this client speaks {}. Pick another server:", server.name, server.protocol_version, PROTOCOL_VERSION);
+            },
+            _ => println!("Enter a number between 1 and {}:", servers.len())
+        }
+    }
+
+    None
+}
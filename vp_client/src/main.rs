@@ -1,9 +1,16 @@
 extern crate vp_shared;
 extern crate bincode;
 extern crate byteorder;
+extern crate time;
 
+mod discovery;
+
+use std::env;
 use std::thread;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::channel;
+use std::collections::HashMap;
 use std::net::TcpStream;
 use std::io::{Read, Write, BufRead, BufReader, BufWriter};
 
@@ -12,10 +19,37 @@ use bincode::rustc_serialize::{encode, decode};
 use byteorder::{WriteBytesExt, ReadBytesExt, BigEndian};
 
 use vp_shared::*;
+use vp_shared::mirror::MirroredWorld;
+use vp_shared::interpolation::{EntityInterpolator, InterpolationSettings};
+
+struct WorldMirror
+{
+    state: MirroredWorld,
+    names: HashMap<PlayerId, String>,
+    /// Periodic scoreboards are only printed after asking for one.
+    scoreboard_requested: Arc<AtomicBool>
+}
+
+enum MirrorUpdate
+{
+    Received(Vec<Event>),
+    /// Commands are predicted as soon as they are sent, without waiting for the server.
+    Sent(PlayerCommand)
+}
 
 fn main()
 {
-    let stream = match TcpStream::connect("192.168.1.52:8000")
+    let address = match env::args().nth(1)
+    {
+        Some(ref arg) if arg == "--discover" => match discovery::choose_server()
+        {
+            Some(address) => address.to_string(),
+            None => return
+        },
+        arg => arg.or(env::var("VP_SERVER").ok()).unwrap_or("127.0.0.1:8000".to_string())
+    };
+
+    let stream = match TcpStream::connect(&address[..])
     {
         Ok(stream) => stream,
         Err(e) =>
@@ -26,6 +60,12 @@ fn main()
     };
 
     let (tx, rx) = channel();
+    let command_tx = tx.clone();
+    let scoreboard_requested = Arc::new(AtomicBool::new(false));
+    let mirror_scoreboard_requested = scoreboard_requested.clone();
+
+    let writer = Arc::new(Mutex::new(BufWriter::new(stream.try_clone().unwrap())));
+    let pong_writer = writer.clone();
 
     let read_stream = stream.try_clone().unwrap();
     thread::spawn(move ||
@@ -42,7 +82,16 @@ fn main()
                 Err(e) => { println!("Error reading events: {}", e); vec![] }
             };
 
-            tx.send(events).unwrap();
+            // answered right away, so the round trip does not include the time spent printing
+            for event in events.iter()
+            {
+                if let Event::Ping(pinged_at) = *event
+                {
+                    send_commands(&mut *pong_writer.lock().unwrap(), &vec![PlayerCommand::Pong(pinged_at)]);
+                }
+            }
+
+            tx.send(MirrorUpdate::Received(events)).unwrap();
         }
     });
 
@@ -51,11 +100,30 @@ fn main()
         let count_step = 100;
         let mut next_step = count_step;
         let mut total_events = 0;
+        let mut world = WorldMirror
+        {
+            state: MirroredWorld::new(),
+            names: HashMap::new(),
+            scoreboard_requested: mirror_scoreboard_requested
+        };
+        let mut interpolator = EntityInterpolator::new(InterpolationSettings::default());
 
         loop
         {
-            let events = rx.recv().unwrap();
+            let events = match rx.recv().unwrap()
+            {
+                MirrorUpdate::Received(events) => events,
+                MirrorUpdate::Sent(command) =>
+                {
+                    world.state.predict_command(time::precise_time_s(), command);
+                    continue;
+                }
+            };
             //println!("{:?}", events);
+            let now = time::precise_time_s();
+            world.apply_events(&events);
+            world.state.apply_events(now, &events);
+            interpolator.push_events(now, &events);
 
             total_events += events.len();
             if total_events >= next_step
@@ -63,11 +131,24 @@ fn main()
                 next_step += count_step;
                 println!("Events: {}", total_events);
                 println!("Sample: {:?}", events);
+                println!("Players: {:?}", world.state.players());
+                if let Some(player_id) = world.state.local_player()
+                {
+                    println!("Predicted {}: {:?}", player_id, world.state.predicted_state(time::precise_time_s()));
+                }
+                println!("Rendered positions: {:?}", interpolator.positions(time::precise_time_s()));
             }
         }
     });
 
-    let mut writer = BufWriter::new(stream);
+    // a token from an earlier run resumes the same player if the server still keeps it
+    let session_token = env::var("VP_SESSION").ok().and_then(|token| token.parse().ok());
+    let name = env::var("VP_NAME").unwrap_or("Player".to_string());
+    if env::var("VP_SPECTATE").is_err()
+    {
+        send_commands(&mut *writer.lock().unwrap(), &vec![PlayerCommand::Join(session_token, name.clone())]);
+    }
+
     let stdin = std::io::stdin();
     for line in stdin.lock().lines()
     {
@@ -75,29 +156,154 @@ fn main()
         let command = match line.trim()
         {
             "q" => std::process::exit(0),
+            "j" => PlayerCommand::Join(session_token, name.clone()),
+            rename if rename.starts_with("n ") => PlayerCommand::Rename(rename[2..].to_string()),
+            chat if chat.starts_with("say ") => PlayerCommand::Chat(ChatChannel::All, chat[4..].to_string()),
+            chat if chat.starts_with("t ") => PlayerCommand::Chat(ChatChannel::Team, chat[2..].to_string()),
+            whisper if whisper.starts_with("w ") =>
+            {
+                let mut parts = whisper[2..].trim().splitn(2, ' ');
+                match (parts.next().and_then(|id| id.parse().ok()), parts.next())
+                {
+                    (Some(player_id), Some(text)) => PlayerCommand::Chat(ChatChannel::Whisper(player_id), text.to_string()),
+                    _ =>
+                    {
+                        println!("Usage: w <player id> <message>");
+                        continue;
+                    }
+                }
+            },
+            "s" => PlayerCommand::Spectate,
+            "score" =>
+            {
+                scoreboard_requested.store(true, Ordering::SeqCst);
+                PlayerCommand::RequestScoreboard
+            },
+            "f" => PlayerCommand::Follow(None),
+            follow if follow.starts_with("f ") => PlayerCommand::Follow(follow[2..].trim().parse().ok()),
             "u" => PlayerCommand::ChangeMovementDirection(Some(Direction::Up)),
             "d" => PlayerCommand::ChangeMovementDirection(Some(Direction::Down)),
             "l" => PlayerCommand::ChangeMovementDirection(Some(Direction::Left)),
             "r" => PlayerCommand::ChangeMovementDirection(Some(Direction::Right)),
+            "U" => PlayerCommand::Fire(Direction::Up),
+            "D" => PlayerCommand::Fire(Direction::Down),
+            "L" => PlayerCommand::Fire(Direction::Left),
+            "R" => PlayerCommand::Fire(Direction::Right),
             _ => PlayerCommand::ChangeMovementDirection(None),
         };
 
         println!("Sending command: {:?}", command);
 
-        let commands = vec![command];
-        let encoded = encode(&commands, SizeLimit::Infinite).unwrap();
-        writer.write_u32::<BigEndian>(encoded.len() as u32).unwrap();
-        writer.write(&encoded).unwrap();
-        writer.flush().unwrap();
+        send_commands(&mut *writer.lock().unwrap(), &vec![command.clone()]);
+        command_tx.send(MirrorUpdate::Sent(command)).unwrap();
+    }
+}
+
+fn send_commands<W: Write>(writer: &mut W, commands: &Vec<PlayerCommand>)
+{
+    let encoded = encode(commands, SizeLimit::Infinite).unwrap();
+    writer.write_u32::<BigEndian>(encoded.len() as u32).unwrap();
+    writer.write(&encoded).unwrap();
+    writer.flush().unwrap();
+}
+
+impl WorldMirror
+{
+    fn apply_events(&mut self, events: &[Event])
+    {
+        for event in events
+        {
+            match *event
+            {
+                Event::PlayerCreated(player_id, ref profile, _) =>
+                {
+                    self.names.insert(player_id, profile.name.clone());
+                },
+                Event::PlayerJoined(_) => {},
+                Event::Chat(ref message) => match (message.sender, message.channel)
+                {
+                    (None, _) => println!("* {}", message.text),
+                    (Some(sender), ChatChannel::All) => println!("{}: {}", self.name(sender), message.text),
+                    (Some(sender), ChatChannel::Team) => println!("[team] {}: {}", self.name(sender), message.text),
+                    (Some(sender), ChatChannel::Whisper(_)) => println!("[whisper] {}: {}", self.name(sender), message.text),
+                },
+                Event::PlayerRenamed(player_id, ref name) =>
+                {
+                    println!("{} is now known as {}", self.name(player_id), name);
+                    self.names.insert(player_id, name.clone());
+                },
+                Event::PlayerRemoved(player_id) =>
+                {
+                    self.names.remove(&player_id);
+                },
+                Event::PlayerActed(_, _) => {},
+                Event::PlayerDamaged(_, _, _) => {},
+                Event::PlayerKilled(ref kill) =>
+                {
+                    let assists: Vec<String> = kill.assists.iter().map(|assist_id| self.name(*assist_id)).collect();
+                    if assists.is_empty()
+                    {
+                        println!("{} [{:?}] {}", self.name(kill.killer), kill.weapon, self.name(kill.victim));
+                    }
+                    else
+                    {
+                        println!("{} + {} [{:?}] {}", self.name(kill.killer), assists.join(" + "), kill.weapon, self.name(kill.victim));
+                    }
+                },
+                Event::ScoreboardUpdated(ref scoreboard) =>
+                {
+                    if self.scoreboard_requested.swap(false, Ordering::SeqCst)
+                    {
+                        print_scoreboard(scoreboard);
+                    }
+                },
+                Event::MatchRestarted => println!("Match restarted"),
+                Event::WorldReset => self.names.clear(),
+                Event::RulesChanged(rules) => println!("Rules changed: {:?}", rules),
+                Event::ServerShuttingDown => println!("Server is shutting down"),
+                Event::SessionStarted(player_id, token) =>
+                {
+                    println!("Playing as player {}, set VP_SESSION={} to resume after a disconnect", player_id, token);
+                },
+                Event::Spectating(Some(player_id)) => println!("Spectating, following player {}", player_id),
+                Event::Spectating(None) => println!("Spectating with a free camera"),
+                Event::CommandRejected(ref reason) => println!("Rejected: {}", reason),
+                Event::Ping(_) => {},
+                Event::ConnectionRejected(reason) =>
+                {
+                    println!("Connection rejected: {:?}", reason);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    fn name(&self, player_id: PlayerId) -> String
+    {
+        self.names.get(&player_id).cloned().unwrap_or(format!("Player {}", player_id))
+    }
+}
+
+fn print_scoreboard(scoreboard: &Scoreboard)
+{
+    println!("Match time {:.0}s", scoreboard.match_time);
+    println!("{:<16} {:>5} {:>6} {:>7} {:>7} {:>8} {:>7}", "Name", "Kills", "Deaths", "Assists", "Damage", "Accuracy", "Alive");
+    for entry in scoreboard.entries.iter()
+    {
+        let stats = &entry.stats;
+        println!
+        (
+            "{:<16} {:>5} {:>6} {:>7} {:>7.0} {:>7.0}% {:>6.0}s",
+            entry.name, stats.kills, stats.deaths, stats.assists, stats.damage_dealt, stats.accuracy() * 100.0, stats.time_alive
+        );
     }
 }
 
 fn read_message<R: ReadBytesExt>(reader: &mut R) -> std::io::Result<Vec<u8>>
 {
     let length = try!(reader.read_u32::<BigEndian>()) as usize;
-    let mut buf = Vec::with_capacity(length);
-    unsafe {buf.set_len(length);}
-    try!(reader.read(&mut buf));
+    let mut buf = vec![0; length];
+    try!(reader.read_exact(&mut buf));
 
     Ok(buf)
 }
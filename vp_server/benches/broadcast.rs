@@ -0,0 +1,90 @@
+#![feature(test)]
+
+extern crate test;
+extern crate bincode;
+extern crate byteorder;
+extern crate nix;
+extern crate vp_shared;
+extern crate vp_server;
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+
+use test::Bencher;
+use bincode::SizeLimit;
+use bincode::rustc_serialize::{encode, encode_into};
+use byteorder::{WriteBytesExt, BigEndian};
+use nix::sys::uio::{writev, IoVec};
+
+use vp_shared::{Event, PlayerAction, Direction};
+use vp_server::game_server::network_loop::{Payload, frame_payload};
+
+const CLIENTS: usize = 32;
+const PLAYERS: usize = 32;
+
+/// A busy tick, everyone changing direction and someone getting hit.
+fn tick_events() -> Vec<Event>
+{
+    let mut events: Vec<Event> = (0..PLAYERS)
+        .map(|player_id| Event::PlayerActed(player_id, PlayerAction::ChangedMovementDirection(Some(Direction::Up))))
+        .collect();
+    events.push(Event::PlayerDamaged(1, 2, 25.0));
+    events
+}
+
+/// Writes go to /dev/null, so the numbers are the cost of preparing and handing over the data.
+fn sink() -> File
+{
+    OpenOptions::new().write(true).open("/dev/null").unwrap()
+}
+
+/// The broadcast path before payloads were shared: the encoded events are copied for every
+/// client, copied again behind a length prefix and written one message at a time.
+#[bench]
+fn broadcast_copied_per_client(b: &mut Bencher)
+{
+    let events = tick_events();
+    let ping = vec![Event::Ping(0)];
+    let mut sink = sink();
+
+    b.iter(||
+    {
+        let encoded_events = encode(&events, SizeLimit::Infinite).unwrap();
+        let encoded_ping = encode(&ping, SizeLimit::Infinite).unwrap();
+
+        for _ in 0..CLIENTS
+        {
+            for data in vec![encoded_events.clone(), encoded_ping.clone()]
+            {
+                let mut message = Vec::with_capacity(4 + data.len());
+                message.write_u32::<BigEndian>(data.len() as u32).unwrap();
+                message.extend(data.into_iter());
+                sink.write_all(&message).unwrap();
+            }
+        }
+    });
+}
+
+/// The current path: every message is framed once, clients queue a reference to it and each
+/// client's queue goes out in a single writev.
+#[bench]
+fn broadcast_shared_with_writev(b: &mut Bencher)
+{
+    let events = tick_events();
+    let ping = vec![Event::Ping(0)];
+    let sink = sink();
+
+    b.iter(||
+    {
+        let framed_events = frame_payload(|buffer| encode_into(&events, buffer, SizeLimit::Infinite).unwrap());
+        let framed_ping = frame_payload(|buffer| encode_into(&ping, buffer, SizeLimit::Infinite).unwrap());
+
+        for _ in 0..CLIENTS
+        {
+            let queue: Vec<Payload> = vec![framed_events.clone(), framed_ping.clone()];
+            let buffers: Vec<IoVec<&[u8]>> = queue.iter().map(|payload| IoVec::from_slice(&payload[..])).collect();
+            writev(sink.as_raw_fd(), &buffers).unwrap();
+        }
+    });
+}
@@ -0,0 +1,334 @@
+use std::io;
+use std::io::BufRead;
+use std::thread;
+use std::sync::mpsc::Sender;
+
+use vp_shared::{Event, PlayerId, PlayerProfile};
+
+use game_server::Frame;
+use game_server::network_loop::{NetworkCommand, ClientId};
+use game_server::admission::IpRange;
+use rules_file;
+use profiles;
+use chat;
+use server_state::ServerState;
+use vp_world::World;
+
+const HELP: &'static str = "\
+Commands:
+  players              list connected players
+  kick <id>            disconnect a player
+  ban <id>             disconnect a player and ban their address
+  banip <address>      ban an address or CIDR range, e.g. 10.0.0.0/8
+  map <name>           change the level and restart the match
+  restart              restart the match
+  addbot               add a player without a connection
+  mute <id>            stop a player from chatting
+  unmute <id>          allow a muted player to chat again
+  say <message>        send a system message to everyone
+  set <rule> <value>   change a gameplay rule
+  pause                pause the simulation
+  resume               resume the simulation
+  stats                print frame timing statistics
+  scores               print the scoreboard of the current match
+  leaderboard          print the players with the most kills over all matches
+  lifetime <name>      print the stats of a player over all matches
+  help                 print this help";
+
+#[derive(Debug)]
+pub enum AdminCommand
+{
+    ListPlayers,
+    Kick(PlayerId),
+    Ban(PlayerId),
+    BanRange(IpRange),
+    ChangeMap(String),
+    RestartMatch,
+    AddBot,
+    Mute(PlayerId),
+    Unmute(PlayerId),
+    Say(String),
+    SetRule(String, String),
+    Pause,
+    Resume,
+    PrintStats,
+    PrintScoreboard,
+    PrintLeaderboard,
+    PrintLifetime(String)
+}
+
+pub fn spawn_stdin_console(sender: Sender<AdminCommand>)
+{
+    thread::spawn(move ||
+    {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines()
+        {
+            let line = match line
+            {
+                Ok(line) => line,
+                Err(e) =>
+                {
+                    error!("Failed to read admin console input, {}", e);
+                    return;
+                }
+            };
+
+            match parse_command(&line)
+            {
+                Ok(Some(command)) => if sender.send(command).is_err() { return; },
+                Ok(None) => {},
+                Err(e) => println!("{}", e)
+            }
+        }
+    });
+}
+
+pub fn parse_command(line: &str) -> Result<Option<AdminCommand>, String>
+{
+    let mut words = line.split_whitespace();
+    let name = match words.next()
+    {
+        Some(name) => name,
+        None => return Ok(None)
+    };
+    let args: Vec<&str> = words.collect();
+
+    let command = match (name, args.len())
+    {
+        ("help", 0)    => return Err(HELP.to_string()),
+        ("players", 0) => AdminCommand::ListPlayers,
+        ("kick", 1)    => AdminCommand::Kick(try!(parse_player_id(args[0]))),
+        ("ban", 1)     => AdminCommand::Ban(try!(parse_player_id(args[0]))),
+        ("banip", 1)   => AdminCommand::BanRange(try!(args[0].parse())),
+        ("map", 1)     => AdminCommand::ChangeMap(args[0].to_string()),
+        ("restart", 0) => AdminCommand::RestartMatch,
+        ("addbot", 0)  => AdminCommand::AddBot,
+        ("mute", 1)    => AdminCommand::Mute(try!(parse_player_id(args[0]))),
+        ("unmute", 1)  => AdminCommand::Unmute(try!(parse_player_id(args[0]))),
+        ("say", n) if n > 0 => AdminCommand::Say(args.join(" ")),
+        ("set", 2)     => AdminCommand::SetRule(args[0].to_string(), args[1].to_string()),
+        ("pause", 0)   => AdminCommand::Pause,
+        ("resume", 0)  => AdminCommand::Resume,
+        ("stats", 0)   => AdminCommand::PrintStats,
+        ("scores", 0)  => AdminCommand::PrintScoreboard,
+        ("leaderboard", 0) => AdminCommand::PrintLeaderboard,
+        ("lifetime", n) if n > 0 => AdminCommand::PrintLifetime(args.join(" ")),
+        _ => return Err(format!("Unknown command '{}', type 'help' for a list of commands", line.trim()))
+    };
+
+    Ok(Some(command))
+}
+
+pub fn execute_commands(world: &mut World, state: &mut ServerState, frame: &Frame) -> (Vec<Event>, Vec<NetworkCommand>)
+{
+    let mut events = Vec::new();
+    let mut network_commands = Vec::new();
+
+    for command in frame.admin_commands.iter()
+    {
+        match *command
+        {
+            AdminCommand::ListPlayers => print_players(world, state),
+            AdminCommand::Kick(player_id) if world.has_player(player_id) =>
+            {
+                println!("Kicking {}", player_id);
+                state.forget_player(world, player_id);
+                if let Some(client_id) = state.sessions.end(player_id)
+                {
+                    network_commands.push(NetworkCommand::Disconnect(client_id));
+                }
+                events.push(chat::system_message(format!("{} was kicked", world.player_name(player_id).unwrap_or("?"))));
+                events.extend(world.remove_player(player_id));
+            },
+            AdminCommand::Ban(player_id) if world.has_player(player_id) => match state.sessions.client_of(player_id)
+            {
+                Some(client_id) =>
+                {
+                    println!("Banning {}", player_id);
+                    state.sessions.end(player_id);
+                    state.forget_player(world, player_id);
+                    network_commands.push(NetworkCommand::Ban(client_id));
+                    events.push(chat::system_message(format!("{} was banned", world.player_name(player_id).unwrap_or("?"))));
+                    events.extend(world.remove_player(player_id));
+                },
+                None => println!("Player {} is not connected, ban its address with banip instead", player_id)
+            },
+            AdminCommand::Kick(player_id) | AdminCommand::Ban(player_id) => println!("No player {}", player_id),
+            AdminCommand::BanRange(range) =>
+            {
+                println!("Banning {}", range);
+                network_commands.push(NetworkCommand::BanRange(range));
+            },
+            AdminCommand::ChangeMap(ref level) =>
+            {
+                println!("Changing map to {}", level);
+                state.finish_match(world);
+                state.level = level.clone();
+                events.extend(world.restart_match());
+            },
+            AdminCommand::RestartMatch =>
+            {
+                println!("Restarting match");
+                state.finish_match(world);
+                events.extend(world.restart_match());
+            },
+            AdminCommand::AddBot =>
+            {
+                let player_id = world.allocate_player_id();
+                let name = profiles::deduplicate_name("Bot", &world.player_names());
+                println!("Adding bot {}, {}", player_id, name);
+                state.bots.insert(player_id);
+                let team = state.team_for_new_player(world);
+                events.extend(world.create_player(player_id, PlayerProfile { name: name, team: team }));
+            },
+            AdminCommand::Mute(player_id) => match world.player_name(player_id)
+            {
+                Some(name) =>
+                {
+                    println!("Muting {}, {}", player_id, name);
+                    state.chat.mute(name);
+                },
+                None => println!("No player {}", player_id)
+            },
+            AdminCommand::Unmute(player_id) => match world.player_name(player_id).map(|name| state.chat.unmute(name))
+            {
+                Some(true) => println!("Unmuted {}", player_id),
+                Some(false) => println!("Player {} is not muted", player_id),
+                None => println!("No player {}", player_id)
+            },
+            AdminCommand::Say(ref text) => events.push(chat::system_message(text.clone())),
+            AdminCommand::SetRule(ref rule, ref value) =>
+            {
+                let mut rules = world.rules();
+                match rules_file::apply_rule(&mut rules, rule, value).and_then(|_| rules_file::validate_rules(&rules))
+                {
+                    Ok(_) =>
+                    {
+                        println!("Rules changed: {:?}", rules);
+                        events.extend(world.change_rules(rules));
+                    },
+                    Err(e) => println!("Rejected: {}", e)
+                }
+            },
+            AdminCommand::Pause =>
+            {
+                println!("Simulation paused");
+                state.paused = true;
+            },
+            AdminCommand::Resume =>
+            {
+                println!("Simulation resumed");
+                state.paused = false;
+            },
+            AdminCommand::PrintStats =>
+            {
+                let stats = frame.stats;
+                println!("Ticks: {}, overruns: {}", stats.ticks, stats.overruns);
+                println!
+                (
+                    "Processing p50/p95/p99: {}/{}/{} us",
+                    stats.processing.p50.num_microseconds().unwrap(),
+                    stats.processing.p95.num_microseconds().unwrap(),
+                    stats.processing.p99.num_microseconds().unwrap()
+                );
+                println!
+                (
+                    "Sleep p50/p95/p99: {}/{}/{} us",
+                    stats.sleep.p50.num_microseconds().unwrap(),
+                    stats.sleep.p95.num_microseconds().unwrap(),
+                    stats.sleep.p99.num_microseconds().unwrap()
+                );
+            },
+            AdminCommand::PrintScoreboard => print_scoreboard(world),
+            AdminCommand::PrintLeaderboard => print_leaderboard(state),
+            AdminCommand::PrintLifetime(ref name) => match state.stats.lifetime(name)
+            {
+                Some(lifetime) =>
+                {
+                    println!("{}, {} matches:", lifetime.name, lifetime.matches);
+                    println!
+                    (
+                        "  kills {}, deaths {}, assists {}, damage {:.0}, accuracy {:.0}%, time alive {:.0}s",
+                        lifetime.kills, lifetime.deaths, lifetime.assists, lifetime.damage_dealt, lifetime.accuracy() * 100.0, lifetime.time_alive
+                    );
+                },
+                None => println!("No recorded matches for {}", name)
+            }
+        }
+    }
+
+    (events, network_commands)
+}
+
+fn print_players(world: &World, state: &ServerState)
+{
+    let players = world.player_states();
+    let spectators = state.client_addresses.len().saturating_sub(state.sessions.connected_players());
+    println!("{} players and {} spectators on {}{}:", players.len(), spectators, state.level, if state.paused { " (paused)" } else { "" });
+
+    for (player_id, player_state) in players
+    {
+        let address = if state.bots.contains(&player_id)
+        {
+            "bot".to_string()
+        }
+        else
+        {
+            state.sessions.client_of(player_id)
+                .and_then(|client_id| state.client_addresses.get(&client_id))
+                .map(|address| address.to_string())
+                .unwrap_or("disconnected".to_string())
+        };
+        let name = world.player_name(player_id).unwrap_or("");
+        let ping = state.sessions.client_of(player_id).map(|client_id| format_ping(state, client_id)).unwrap_or("-".to_string());
+        println!("  {:>4}  {:<16}  {:<21}  {:>7}  {:?}", player_id, name, address, ping, player_state.position);
+    }
+
+    for (client_id, address) in state.client_addresses.iter().filter(|&(client_id, _)| state.sessions.player_of(*client_id).is_none())
+    {
+        let camera = state.spectators.following(*client_id).map(|player_id| format!("following {}", player_id)).unwrap_or("free camera".to_string());
+        println!("     -  {:<16}  {:<21}  {:>7}  spectating, {}", "", address.to_string(), format_ping(state, *client_id), camera);
+    }
+}
+
+fn format_ping(state: &ServerState, client_id: ClientId) -> String
+{
+    state.pings.get(&client_id).map(|ping| format!("{} ms", ping)).unwrap_or("-".to_string())
+}
+
+fn print_scoreboard(world: &World)
+{
+    let scoreboard = world.scoreboard();
+    println!("Match time {:.0}s:", scoreboard.match_time);
+
+    for entry in scoreboard.entries.iter()
+    {
+        let stats = &entry.stats;
+        println!
+        (
+            "  {:>4}  {:<16}  {:>3} kills  {:>3} deaths  {:>3} assists  {:>6.0} damage  {:>3.0}% accuracy",
+            entry.player_id, entry.name, stats.kills, stats.deaths, stats.assists, stats.damage_dealt, stats.accuracy() * 100.0
+        );
+    }
+}
+
+fn print_leaderboard(state: &ServerState)
+{
+    let leaderboard = state.stats.leaderboard(10);
+    println!("Leaderboard over {} matches:", state.stats.matches_recorded());
+
+    for (rank, lifetime) in leaderboard.iter().enumerate()
+    {
+        println!
+        (
+            "  {:>2}. {:<16}  {:>5} kills  {:>5} deaths  {:>4} matches",
+            rank + 1, lifetime.name, lifetime.kills, lifetime.deaths, lifetime.matches
+        );
+    }
+}
+
+fn parse_player_id(id: &str) -> Result<PlayerId, String>
+{
+    id.parse().map_err(|_| format!("Invalid player id '{}'", id))
+}
@@ -0,0 +1,332 @@
+extern crate time;
+#[macro_use]
+extern crate log;
+extern crate env_logger;
+extern crate getopts;
+extern crate bincode;
+extern crate vp_shared;
+extern crate vp_server;
+
+use std::env;
+use std::io;
+use std::io::BufRead;
+use std::thread;
+use std::process;
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Sender, Receiver};
+
+use getopts::Options;
+use bincode::SizeLimit;
+use bincode::rustc_serialize::encode_into;
+
+use vp_shared::Event;
+use vp_server::game_server;
+use vp_server::game_server::GameServerCommand;
+use vp_server::game_server::network_loop::{NetworkEvent, NetworkCommand, ClientId, Payload, frame_payload};
+use vp_server::config::ServerConfig;
+use vp_server::playback::{Replay, Playback};
+use vp_server::{signals, metrics};
+
+const HELP: &'static str = "\
+Commands:
+  seek <tick>          jump to a tick
+  speed <factor>       change the playback speed, e.g. 0.5 or 2
+  pause                pause playback
+  resume               resume playback
+  status               print the playback position
+  help                 print this help";
+
+enum PlaybackCommand
+{
+    Seek(u64),
+    Speed(f64),
+    Pause,
+    Resume,
+    Status
+}
+
+fn main()
+{
+    env_logger::init().ok().expect("Failed to init logger");
+
+    let args: Vec<String> = env::args().collect();
+    let mut options = Options::new();
+    options.optopt("b", "bind-address", "address and port spectators connect to", "ADDRESS");
+    options.optopt("", "max-clients", "maximum number of connected spectators", "COUNT");
+    options.optopt("", "speed", "playback speed, 1 is real time", "FACTOR");
+    options.optopt("", "start", "tick to start playing from", "TICK");
+    options.optflag("", "info", "print what the replay contains and exit");
+    options.optflag("h", "help", "print this help");
+
+    let matches = match options.parse(&args[1..])
+    {
+        Ok(matches) => matches,
+        Err(e) =>
+        {
+            error!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    let brief = format!("Usage: {} [options] REPLAY", args[0]);
+    if matches.opt_present("help") || matches.free.len() != 1
+    {
+        println!("{}", options.usage(&brief));
+        return;
+    }
+
+    let replay = match Replay::load(&matches.free[0])
+    {
+        Ok(replay) => replay,
+        Err(e) =>
+        {
+            error!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    print_info(&replay);
+    if matches.opt_present("info")
+    {
+        return;
+    }
+
+    let mut config = ServerConfig::default();
+    config.tick_rate = replay.header.tick_rate;
+    if let Some(address) = matches.opt_str("bind-address")
+    {
+        config.bind_address = parse_option("bind-address", &address);
+    }
+    if let Some(max_clients) = matches.opt_str("max-clients")
+    {
+        config.max_clients = parse_option("max-clients", &max_clients);
+    }
+    if let Err(e) = config.validate()
+    {
+        error!("{}", e);
+        process::exit(2);
+    }
+
+    let mut playback = Playback::new(replay);
+    if let Some(speed) = matches.opt_str("speed")
+    {
+        playback.speed = parse_option("speed", &speed);
+        if !(playback.speed > 0.0)
+        {
+            error!("Invalid value '{}' for --speed, expected a positive number", speed);
+            process::exit(2);
+        }
+    }
+    if let Some(tick) = matches.opt_str("start")
+    {
+        playback.seek(parse_option("start", &tick));
+    }
+
+    signals::install_shutdown_handler();
+
+    let admission = config.admission_policy().ok().expect("Default admission policy is valid");
+    let timing = config.loop_timing();
+    let (mut game_loop, network_loop) = game_server::game_server(timing, config.network_settings(), admission, metrics::shared());
+
+    let network_thread = thread::spawn(move ||
+    {
+        info!("Serving the replay to spectators on {}...", config.bind_address);
+        network_loop.run()
+    });
+
+    let commands = spawn_stdin_console();
+    let mut reported_finish = false;
+
+    game_loop.run(|frame|
+    {
+        if signals::shutdown_requested()
+        {
+            let sends = get_broadcast_sends(&vec![Event::ServerShuttingDown], &frame.currently_connected_clients);
+            return GameServerCommand::Exit(vec![NetworkCommand::Send(sends)]);
+        }
+
+        let jumped = execute_commands(&mut playback, &commands);
+        let events = playback.advance();
+
+        if playback.finished() && !reported_finish
+        {
+            println!("Replay finished at tick {}, seek to watch again", playback.tick());
+        }
+        reported_finish = playback.finished();
+
+        // clients only ever watch, whatever they send is ignored
+        let mut sends = Vec::new();
+        for message in frame.messages.iter()
+        {
+            if let NetworkEvent::ClientConnected(client_id, _) = *message
+            {
+                sends.push((client_id, serialize_events(&vec![Event::Spectating(None)])));
+            }
+        }
+
+        let snapshot_clients: Vec<ClientId> = if jumped
+        {
+            frame.currently_connected_clients.clone()
+        }
+        else
+        {
+            frame.get_clients_needing_snapshot()
+        };
+
+        if snapshot_clients.len() != 0
+        {
+            let snapshot = serialize_events(&playback.snapshot());
+            sends.extend(snapshot_clients.iter().map(|client_id| (*client_id, snapshot.clone())));
+        }
+
+        if events.len() != 0
+        {
+            let update_clients: Vec<ClientId> = frame.currently_connected_clients.iter().filter(|client_id| !snapshot_clients.contains(client_id)).cloned().collect();
+            sends.extend(get_broadcast_sends(&events, &update_clients));
+        }
+
+        GameServerCommand::Continue(vec![NetworkCommand::Send(sends)])
+    });
+
+    network_thread.join().ok().expect("Network thread panicked");
+}
+
+fn print_info(replay: &Replay)
+{
+    let header = &replay.header;
+    let duration = replay.last_tick().checked_sub(replay.first_tick()).unwrap_or(0) / header.tick_rate as u64;
+    println!("{} on {}, recorded at {} ticks per second", header.game_mode, header.level, header.tick_rate);
+    println!
+    (
+        "Ticks {} to {} ({}m {}s), {} keyframes",
+        replay.first_tick(), replay.last_tick(), duration / 60, duration % 60, replay.keyframe_count()
+    );
+}
+
+fn parse_option<T: FromStr>(name: &str, value: &str) -> T
+{
+    match value.parse()
+    {
+        Ok(value) => value,
+        Err(_) =>
+        {
+            error!("Invalid value '{}' for --{}", value, name);
+            process::exit(2);
+        }
+    }
+}
+
+/// Returns whether clients need a fresh snapshot because playback jumped.
+fn execute_commands(playback: &mut Playback, commands: &Receiver<PlaybackCommand>) -> bool
+{
+    let mut jumped = false;
+
+    while let Ok(command) = commands.try_recv()
+    {
+        match command
+        {
+            PlaybackCommand::Seek(tick) =>
+            {
+                playback.seek(tick);
+                println!("Seeked to tick {}", playback.tick());
+                jumped = true;
+            },
+            PlaybackCommand::Speed(speed) =>
+            {
+                playback.speed = speed;
+                println!("Playing at {}x", speed);
+            },
+            PlaybackCommand::Pause =>
+            {
+                playback.paused = true;
+                println!("Playback paused at tick {}", playback.tick());
+            },
+            PlaybackCommand::Resume =>
+            {
+                playback.paused = false;
+                println!("Playback resumed at tick {}", playback.tick());
+            },
+            PlaybackCommand::Status =>
+            {
+                let replay = playback.replay();
+                println!
+                (
+                    "Tick {} of {} to {}, {}x{}",
+                    playback.tick(), replay.first_tick(), replay.last_tick(), playback.speed, if playback.paused { ", paused" } else { "" }
+                );
+            }
+        }
+    }
+
+    jumped
+}
+
+fn spawn_stdin_console() -> Receiver<PlaybackCommand>
+{
+    let (sender, receiver) = channel();
+    thread::spawn(move || read_commands(sender));
+    receiver
+}
+
+fn read_commands(sender: Sender<PlaybackCommand>)
+{
+    let stdin = io::stdin();
+    for line in stdin.lock().lines()
+    {
+        let line = match line
+        {
+            Ok(line) => line,
+            Err(e) =>
+            {
+                error!("Failed to read console input, {}", e);
+                return;
+            }
+        };
+
+        match parse_command(&line)
+        {
+            Ok(Some(command)) => if sender.send(command).is_err() { return; },
+            Ok(None) => {},
+            Err(e) => println!("{}", e)
+        }
+    }
+}
+
+fn parse_command(line: &str) -> Result<Option<PlaybackCommand>, String>
+{
+    let mut words = line.split_whitespace();
+    let name = match words.next()
+    {
+        Some(name) => name,
+        None => return Ok(None)
+    };
+    let args: Vec<&str> = words.collect();
+
+    let command = match (name, args.len())
+    {
+        ("help", 0)   => return Err(HELP.to_string()),
+        ("seek", 1)   => PlaybackCommand::Seek(try!(args[0].parse().map_err(|_| format!("Invalid tick '{}'", args[0])))),
+        ("speed", 1)  => match args[0].parse()
+        {
+            Ok(speed) if speed > 0.0 => PlaybackCommand::Speed(speed),
+            _ => return Err(format!("Invalid speed '{}', expected a positive number", args[0]))
+        },
+        ("pause", 0)  => PlaybackCommand::Pause,
+        ("resume", 0) => PlaybackCommand::Resume,
+        ("status", 0) => PlaybackCommand::Status,
+        _ => return Err(format!("Unknown command '{}', type 'help' for a list of commands", line.trim()))
+    };
+
+    Ok(Some(command))
+}
+
+fn get_broadcast_sends(events: &Vec<Event>, client_ids: &[ClientId]) -> Vec<(ClientId, Payload)>
+{
+    let payload = serialize_events(events);
+    client_ids.iter().map(|client_id| (*client_id, payload.clone())).collect()
+}
+
+fn serialize_events(events: &Vec<Event>) -> Payload
+{
+    frame_payload(|buffer| encode_into(events, buffer, SizeLimit::Infinite).unwrap())
+}
@@ -0,0 +1,185 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use vp_shared::{Event, PlayerId, ChatChannel, ChatMessage};
+
+pub const MAX_MESSAGE_LENGTH: usize = 200;
+const RATE_LIMIT_MESSAGES: usize = 5;
+const RATE_LIMIT_SECONDS: u64 = 5;
+
+/// Server side chat policy: mutes and per player rate limits. Mutes are kept by name ignoring
+/// case, so leaving and joining again or reconnecting does not lift them.
+pub struct Chat
+{
+    muted: HashSet<String>,
+    recent_messages: HashMap<PlayerId, VecDeque<u64>>,
+    window_ticks: u64
+}
+
+impl Chat
+{
+    pub fn new(tick_rate: u32) -> Chat
+    {
+        Chat
+        {
+            muted: HashSet::new(),
+            recent_messages: HashMap::new(),
+            window_ticks: RATE_LIMIT_SECONDS * tick_rate as u64
+        }
+    }
+
+    pub fn mute(&mut self, name: &str) -> bool
+    {
+        self.muted.insert(name.to_lowercase())
+    }
+
+    pub fn unmute(&mut self, name: &str) -> bool
+    {
+        self.muted.remove(&name.to_lowercase())
+    }
+
+    pub fn is_muted(&self, name: &str) -> bool
+    {
+        self.muted.contains(&name.to_lowercase())
+    }
+
+    /// Moves a mute along with a rename, otherwise renaming would lift it.
+    pub fn renamed(&mut self, old_name: &str, new_name: &str)
+    {
+        if self.unmute(old_name)
+        {
+            self.mute(new_name);
+        }
+    }
+
+    /// Returns the cleaned up text if the player may send it now.
+    pub fn check(&mut self, player_id: PlayerId, name: &str, text: &str, tick: u64) -> Result<String, String>
+    {
+        if self.is_muted(name)
+        {
+            return Err("You are muted".to_string());
+        }
+
+        let text: String = text.chars().filter(|c| !c.is_control()).collect();
+        let text = text.trim();
+        if text.len() == 0
+        {
+            return Err("Empty message".to_string());
+        }
+
+        if text.chars().count() > MAX_MESSAGE_LENGTH
+        {
+            return Err(format!("Messages can be at most {} characters long", MAX_MESSAGE_LENGTH));
+        }
+
+        let window_ticks = self.window_ticks;
+        let recent = self.recent_messages.entry(player_id).or_insert(VecDeque::new());
+        while recent.front().map(|&sent_tick| sent_tick + window_ticks <= tick).unwrap_or(false)
+        {
+            recent.pop_front();
+        }
+
+        if recent.len() >= RATE_LIMIT_MESSAGES
+        {
+            return Err("Sending messages too fast, slow down".to_string());
+        }
+
+        recent.push_back(tick);
+        Ok(text.to_string())
+    }
+
+    pub fn forget(&mut self, player_id: PlayerId)
+    {
+        self.recent_messages.remove(&player_id);
+    }
+}
+
+pub fn system_message(text: String) -> Event
+{
+    Event::Chat(ChatMessage { channel: ChatChannel::All, sender: None, text: text })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use vp_shared::{Event, ChatChannel};
+    use super::{Chat, system_message, MAX_MESSAGE_LENGTH, RATE_LIMIT_MESSAGES};
+
+    #[test]
+    fn messages_are_cleaned_up()
+    {
+        let mut chat = Chat::new(50);
+        assert_eq!(chat.check(1, "Player", "  hello\u{7} there\n ", 0), Ok("hello there".to_string()));
+        assert!(chat.check(1, "Player", " \t\r\n ", 0).is_err());
+        assert!(chat.check(1, "Player", "", 0).is_err());
+    }
+
+    #[test]
+    fn long_messages_are_rejected()
+    {
+        let mut chat = Chat::new(50);
+        let longest: String = (0..MAX_MESSAGE_LENGTH).map(|_| 'ä').collect();
+        assert!(chat.check(1, "Player", &longest, 0).is_ok());
+        assert!(chat.check(1, "Player", &format!("{}a", longest), 0).is_err());
+    }
+
+    #[test]
+    fn players_sending_too_fast_are_limited()
+    {
+        let mut chat = Chat::new(10);
+        for _ in 0..RATE_LIMIT_MESSAGES
+        {
+            assert!(chat.check(1, "Player", "spam", 0).is_ok());
+        }
+        assert!(chat.check(1, "Player", "spam", 49).is_err());
+        assert!(chat.check(2, "Other", "not spam", 49).is_ok());
+
+        // the window is 5 seconds at 10 ticks per second
+        assert!(chat.check(1, "Player", "later", 50).is_ok());
+    }
+
+    #[test]
+    fn mutes_survive_rejoining_under_the_same_name()
+    {
+        let mut chat = Chat::new(50);
+        assert!(chat.mute("Troll"));
+        assert!(!chat.mute("troll"));
+        assert!(chat.check(1, "Troll", "hi", 0).is_err());
+
+        // leaving forgets the player, joining again gets a new player id
+        chat.forget(1);
+        assert!(chat.check(2, "TROLL", "hi again", 100).is_err());
+        assert!(chat.check(3, "Someone", "hi", 100).is_ok());
+
+        assert!(chat.unmute("troll"));
+        assert!(!chat.unmute("troll"));
+        assert!(chat.check(2, "Troll", "sorry", 100).is_ok());
+    }
+
+    #[test]
+    fn mutes_follow_renames()
+    {
+        let mut chat = Chat::new(50);
+        chat.mute("Troll");
+        chat.renamed("Troll", "Innocent");
+        assert!(!chat.is_muted("Troll"));
+        assert!(chat.is_muted("innocent"));
+
+        chat.renamed("Someone", "Someone Else");
+        assert!(!chat.is_muted("Someone Else"));
+    }
+
+    #[test]
+    fn system_messages_have_no_sender()
+    {
+        match system_message("Server restarting".to_string())
+        {
+            Event::Chat(message) =>
+            {
+                assert_eq!(message.sender, None);
+                assert_eq!(message.channel, ChatChannel::All);
+                assert_eq!(message.text, "Server restarting");
+            },
+            event => panic!("unexpected event {:?}", event)
+        }
+    }
+}
@@ -0,0 +1,519 @@
+use std::env;
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::fs::File;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use getopts::Options;
+use toml;
+use time::Duration;
+
+use vp_shared::simulation::Rules;
+use vp_shared::discovery::DISCOVERY_PORT;
+
+use game_server::LoopTiming;
+use game_server::network_loop::{NetworkSettings, SlowConsumerPolicy};
+use game_server::admission::{AdmissionPolicy, BanList, IpRange};
+use game_server::admission;
+
+use rules_file;
+use stats_store::StatsStore;
+use recording::RecordingSettings;
+
+const ENV_PREFIX: &'static str = "VP_";
+
+const SETTINGS: &'static [(&'static str, &'static str)] =
+&[
+    ("server_name",  "name shown in server browsers"),
+    ("bind_address", "address and port to listen on"),
+    ("tick_rate",    "simulation ticks per second"),
+    ("max_catch_up_ticks", "ticks run back to back to catch up after a stall before time is dropped"),
+    ("spin_wait_microseconds", "how long before a tick the loop stops sleeping and spins for precision"),
+    ("max_clients",  "maximum number of connected clients"),
+    ("max_connections_per_ip", "maximum number of connections from a single address"),
+    ("reserved_slots", "client slots only admin addresses may use"),
+    ("admin_addresses", "comma separated addresses or CIDR ranges allowed into reserved slots"),
+    ("ban_list_file", "file of banned addresses and CIDR ranges, new bans are appended to it"),
+    ("stats_file", "file finished match results are appended to, for lifetime stats and leaderboards"),
+    ("replay_directory", "directory matches are recorded to, recording is off without it"),
+    ("replay_keyframe_seconds", "how often replays get a snapshot to seek to"),
+    ("replay_max_file_megabytes", "size at which a replay continues in a new file"),
+    ("replay_keep_files", "number of replay files kept, the oldest are removed"),
+    ("session_grace_seconds", "how long a disconnected player is kept for its client to resume it"),
+    ("spectator_delay_seconds", "how far behind the live game spectators are kept"),
+    ("rename_cooldown_seconds", "how long a player has to wait between renames"),
+    ("scoreboard_interval_seconds", "how often the scoreboard is sent to everyone, 0 to only send it on request"),
+    ("max_queued_bytes", "maximum bytes queued for a client before it counts as a slow consumer"),
+    ("slow_consumer_policy", "what to do with slow consumers (resync, disconnect)"),
+    ("max_messages_per_second", "messages a client may send per second before they are dropped"),
+    ("max_bytes_per_second", "bytes a client may send per second before messages are dropped"),
+    ("max_commands_per_tick", "commands applied per client each tick, the rest are dropped"),
+    ("http_address", "local address for the /metrics and /status HTTP endpoints"),
+    ("discovery_enabled", "whether LAN discovery queries are answered, on the interface of bind_address"),
+    ("discovery_port", "UDP port answering LAN discovery queries"),
+    ("level",        "level to load"),
+    ("game_mode",    "game mode (deathmatch, team_deathmatch)"),
+    ("rules_file",   "gameplay rules file, reloaded when it changes"),
+    ("player_speed", "player movement speed, units per second"),
+    ("player_max_health", "health players spawn with"),
+    ("weapon_damage", "damage dealt by a hit"),
+    ("weapon_range", "how far shots reach"),
+    ("fire_cooldown_seconds", "minimum time between two shots of a player"),
+    ("respawn_seconds", "how long killed players wait before respawning"),
+];
+
+pub struct ServerConfig
+{
+    pub server_name: String,
+    pub bind_address: SocketAddr,
+    pub tick_rate: u32,
+    pub max_catch_up_ticks: u32,
+    pub spin_wait_microseconds: u32,
+    pub max_clients: usize,
+    pub max_connections_per_ip: usize,
+    pub reserved_slots: usize,
+    pub admin_addresses: Vec<IpRange>,
+    pub ban_list_file: Option<String>,
+    pub stats_file: Option<String>,
+    pub replay_directory: Option<String>,
+    pub replay_keyframe_seconds: u32,
+    pub replay_max_file_megabytes: u32,
+    pub replay_keep_files: usize,
+    pub session_grace_seconds: u32,
+    pub spectator_delay_seconds: u32,
+    pub rename_cooldown_seconds: u32,
+    pub scoreboard_interval_seconds: u32,
+    pub max_queued_bytes: usize,
+    pub slow_consumer_policy: SlowConsumerPolicy,
+    pub max_messages_per_second: u32,
+    pub max_bytes_per_second: u32,
+    pub max_commands_per_tick: usize,
+    pub http_address: SocketAddr,
+    pub discovery_enabled: bool,
+    pub discovery_port: u16,
+    pub level: String,
+    pub game_mode: GameMode,
+    pub rules_file: Option<String>,
+    /// Rules set in the environment or on the command line, reapplied whenever the rules file is reloaded.
+    pub rule_overrides: Vec<(String, String)>,
+    pub rules: Rules
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameMode
+{
+    Deathmatch,
+    TeamDeathmatch
+}
+
+#[derive(Debug)]
+pub enum ConfigError
+{
+    HelpRequested(String),
+    Arguments(String),
+    File(String, io::Error),
+    FileSyntax(String, String),
+    InvalidValue(String, String, String),
+    Validation(String)
+}
+
+impl Default for ServerConfig
+{
+    fn default() -> ServerConfig
+    {
+        ServerConfig
+        {
+            server_name: "Venus Prime".to_string(),
+            bind_address: FromStr::from_str("0.0.0.0:8000").unwrap(),
+            tick_rate: 50,
+            max_catch_up_ticks: 5,
+            spin_wait_microseconds: 2000,
+            max_clients: 128,
+            max_connections_per_ip: 4,
+            reserved_slots: 0,
+            admin_addresses: vec![],
+            ban_list_file: None,
+            stats_file: None,
+            replay_directory: None,
+            replay_keyframe_seconds: 10,
+            replay_max_file_megabytes: 64,
+            replay_keep_files: 50,
+            session_grace_seconds: 60,
+            spectator_delay_seconds: 0,
+            rename_cooldown_seconds: 10,
+            scoreboard_interval_seconds: 5,
+            max_queued_bytes: 256 * 1024,
+            slow_consumer_policy: SlowConsumerPolicy::Resync,
+            max_messages_per_second: 100,
+            max_bytes_per_second: 16 * 1024,
+            max_commands_per_tick: 8,
+            http_address: FromStr::from_str("127.0.0.1:9100").unwrap(),
+            discovery_enabled: false,
+            discovery_port: DISCOVERY_PORT,
+            level: "arena".to_string(),
+            game_mode: GameMode::Deathmatch,
+            rules_file: None,
+            rule_overrides: vec![],
+            rules: Rules::default()
+        }
+    }
+}
+
+impl ServerConfig
+{
+    pub fn load() -> Result<ServerConfig, ConfigError>
+    {
+        let args: Vec<String> = env::args().collect();
+        ServerConfig::load_from(&args)
+    }
+
+    pub fn load_from(args: &[String]) -> Result<ServerConfig, ConfigError>
+    {
+        let options = command_line_options();
+        let matches = try!(options.parse(&args[1..]).map_err(|e| ConfigError::Arguments(e.to_string())));
+
+        if matches.opt_present("help")
+        {
+            let brief = format!("Usage: {} [options]", args[0]);
+            return Err(ConfigError::HelpRequested(options.usage(&brief)));
+        }
+
+        let mut config = ServerConfig::default();
+
+        let config_path = matches.opt_str("config").or(env::var(format!("{}CONFIG", ENV_PREFIX)).ok());
+        if let Some(path) = config_path
+        {
+            try!(config.apply_file(&path));
+        }
+
+        let mut overrides = Vec::new();
+        for &(key, _) in SETTINGS
+        {
+            let env_name = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+            if let Ok(value) = env::var(&env_name)
+            {
+                overrides.push((key, value, env_name));
+            }
+        }
+
+        for &(key, _) in SETTINGS
+        {
+            if let Some(value) = matches.opt_str(&option_name(key))
+            {
+                overrides.push((key, value, "command line".to_string()));
+            }
+        }
+
+        // the rules file is a file like the config file, so environment and command line rules win over it
+        let rules_path = overrides.iter().rev().find(|&&(key, _, _)| key == "rules_file").map(|&(_, ref value, _)| value.clone()).or(config.rules_file.clone());
+        if let Some(ref path) = rules_path
+        {
+            config.rules = try!(rules_file::load_rules(path, &config.rules, &[]).map_err(ConfigError::Validation));
+        }
+
+        config.rule_overrides = overrides.iter()
+            .filter(|&&(key, _, _)| rules_file::is_rule(key))
+            .map(|&(key, ref value, _)| (key.to_string(), value.clone()))
+            .collect();
+
+        for (key, value, source) in overrides
+        {
+            try!(config.apply_setting(key, &value, &source));
+        }
+
+        try!(config.validate());
+
+        Ok(config)
+    }
+
+    pub fn network_settings(&self) -> NetworkSettings
+    {
+        NetworkSettings
+        {
+            address: self.bind_address,
+            max_clients: self.max_clients,
+            max_queued_bytes: self.max_queued_bytes,
+            slow_consumer_policy: self.slow_consumer_policy,
+            max_messages_per_second: self.max_messages_per_second,
+            max_bytes_per_second: self.max_bytes_per_second
+        }
+    }
+
+    pub fn recording_settings(&self) -> Option<RecordingSettings>
+    {
+        self.replay_directory.as_ref().map(|directory| RecordingSettings
+        {
+            directory: directory.clone(),
+            tick_rate: self.tick_rate,
+            keyframe_ticks: self.replay_keyframe_seconds as u64 * self.tick_rate as u64,
+            max_file_bytes: self.replay_max_file_megabytes as u64 * 1024 * 1024,
+            keep_files: self.replay_keep_files
+        })
+    }
+
+    pub fn stats_store(&self) -> Result<StatsStore, ConfigError>
+    {
+        match self.stats_file
+        {
+            Some(ref path) => StatsStore::load(path).map_err(ConfigError::Validation),
+            None => Ok(StatsStore::empty())
+        }
+    }
+
+    pub fn admission_policy(&self) -> Result<AdmissionPolicy, ConfigError>
+    {
+        let ban_list = match self.ban_list_file
+        {
+            Some(ref path) => try!(BanList::load(path).map_err(ConfigError::Validation)),
+            None => BanList::empty()
+        };
+
+        Ok(AdmissionPolicy
+        {
+            ban_list: ban_list,
+            admin_addresses: self.admin_addresses.clone(),
+            max_connections_per_ip: self.max_connections_per_ip,
+            reserved_slots: self.reserved_slots
+        })
+    }
+
+    pub fn loop_timing(&self) -> LoopTiming
+    {
+        LoopTiming
+        {
+            target_frame_time: Duration::microseconds(self.frame_time_microseconds()),
+            max_catch_up_ticks: self.max_catch_up_ticks,
+            spin_wait: Duration::microseconds(self.spin_wait_microseconds as i64)
+        }
+    }
+
+    pub fn frame_time_microseconds(&self) -> i64
+    {
+        1_000_000 / self.tick_rate as i64
+    }
+
+    fn apply_file(&mut self, path: &str) -> Result<(), ConfigError>
+    {
+        let mut contents = String::new();
+        try!(File::open(path).and_then(|mut file| file.read_to_string(&mut contents)).map_err(|e| ConfigError::File(path.to_string(), e)));
+
+        let mut parser = toml::Parser::new(&contents);
+        let table = match parser.parse()
+        {
+            Some(table) => table,
+            None =>
+            {
+                let errors: Vec<String> = parser.errors.iter().map(|e| e.desc.clone()).collect();
+                return Err(ConfigError::FileSyntax(path.to_string(), errors.join("; ")));
+            }
+        };
+
+        for (key, value) in table.iter()
+        {
+            match *value
+            {
+                toml::Value::Table(ref rules) if key == "rules" =>
+                {
+                    for (rule_key, rule_value) in rules.iter()
+                    {
+                        try!(self.apply_setting(rule_key, &value_to_string(rule_value), path));
+                    }
+                },
+                _ => try!(self.apply_setting(key, &value_to_string(value), path))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn apply_setting(&mut self, key: &str, value: &str, source: &str) -> Result<(), ConfigError>
+    {
+        let invalid = |reason: &str| ConfigError::InvalidValue(format!("{} (from {})", key, source), value.to_string(), reason.to_string());
+
+        match key
+        {
+            "server_name"  => self.server_name = value.to_string(),
+            "bind_address" => self.bind_address = try!(FromStr::from_str(value).map_err(|_| invalid("expected host:port"))),
+            "tick_rate"    => self.tick_rate = try!(value.parse().map_err(|_| invalid("expected a positive integer"))),
+            "max_catch_up_ticks" => self.max_catch_up_ticks = try!(value.parse().map_err(|_| invalid("expected a positive integer"))),
+            "spin_wait_microseconds" => self.spin_wait_microseconds = try!(value.parse().map_err(|_| invalid("expected a non-negative integer"))),
+            "max_clients"  => self.max_clients = try!(value.parse().map_err(|_| invalid("expected a positive integer"))),
+            "max_connections_per_ip" => self.max_connections_per_ip = try!(value.parse().map_err(|_| invalid("expected a positive integer"))),
+            "reserved_slots" => self.reserved_slots = try!(value.parse().map_err(|_| invalid("expected a non-negative integer"))),
+            "admin_addresses" => self.admin_addresses = try!(admission::parse_ranges(value).map_err(|e| invalid(&e))),
+            "ban_list_file" => self.ban_list_file = Some(value.to_string()),
+            "stats_file" => self.stats_file = Some(value.to_string()),
+            "replay_directory" => self.replay_directory = Some(value.to_string()),
+            "replay_keyframe_seconds" => self.replay_keyframe_seconds = try!(value.parse().map_err(|_| invalid("expected a positive integer"))),
+            "replay_max_file_megabytes" => self.replay_max_file_megabytes = try!(value.parse().map_err(|_| invalid("expected a positive integer"))),
+            "replay_keep_files" => self.replay_keep_files = try!(value.parse().map_err(|_| invalid("expected a positive integer"))),
+            "session_grace_seconds" => self.session_grace_seconds = try!(value.parse().map_err(|_| invalid("expected a non-negative integer"))),
+            "spectator_delay_seconds" => self.spectator_delay_seconds = try!(value.parse().map_err(|_| invalid("expected a non-negative integer"))),
+            "rename_cooldown_seconds" => self.rename_cooldown_seconds = try!(value.parse().map_err(|_| invalid("expected a non-negative integer"))),
+            "scoreboard_interval_seconds" => self.scoreboard_interval_seconds = try!(value.parse().map_err(|_| invalid("expected a non-negative integer"))),
+            "max_queued_bytes" => self.max_queued_bytes = try!(value.parse().map_err(|_| invalid("expected a positive integer"))),
+            "slow_consumer_policy" => self.slow_consumer_policy = try!(parse_slow_consumer_policy(value).map_err(|_| invalid("expected resync or disconnect"))),
+            "max_messages_per_second" => self.max_messages_per_second = try!(value.parse().map_err(|_| invalid("expected a positive integer"))),
+            "max_bytes_per_second" => self.max_bytes_per_second = try!(value.parse().map_err(|_| invalid("expected a positive integer"))),
+            "max_commands_per_tick" => self.max_commands_per_tick = try!(value.parse().map_err(|_| invalid("expected a positive integer"))),
+            "http_address" => self.http_address = try!(FromStr::from_str(value).map_err(|_| invalid("expected host:port"))),
+            "discovery_enabled" => self.discovery_enabled = try!(value.parse().map_err(|_| invalid("expected true or false"))),
+            "discovery_port" => self.discovery_port = try!(value.parse().map_err(|_| invalid("expected a port number"))),
+            "level"        => self.level = value.to_string(),
+            "game_mode"    => self.game_mode = try!(value.parse().map_err(|_| invalid("expected deathmatch or team_deathmatch"))),
+            "rules_file"   => self.rules_file = Some(value.to_string()),
+            _ if rules_file::is_rule(key) => try!(rules_file::apply_rule(&mut self.rules, key, value).map_err(|e| invalid(&e))),
+            _ => return Err(invalid("unknown setting"))
+        };
+
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError>
+    {
+        if self.tick_rate == 0 || self.tick_rate > 1000
+        {
+            return Err(ConfigError::Validation(format!("tick_rate must be between 1 and 1000, got {}", self.tick_rate)));
+        }
+
+        if self.max_catch_up_ticks == 0
+        {
+            return Err(ConfigError::Validation("max_catch_up_ticks must be at least 1".to_string()));
+        }
+
+        if self.spin_wait_microseconds as i64 >= self.frame_time_microseconds()
+        {
+            return Err(ConfigError::Validation(format!("spin_wait_microseconds must be less than a tick ({} us), got {}", self.frame_time_microseconds(), self.spin_wait_microseconds)));
+        }
+
+        if self.max_clients == 0
+        {
+            return Err(ConfigError::Validation("max_clients must be at least 1".to_string()));
+        }
+
+        if self.max_connections_per_ip == 0
+        {
+            return Err(ConfigError::Validation("max_connections_per_ip must be at least 1".to_string()));
+        }
+
+        if self.reserved_slots >= self.max_clients
+        {
+            return Err(ConfigError::Validation(format!("reserved_slots must be less than max_clients ({}), got {}", self.max_clients, self.reserved_slots)));
+        }
+
+        if self.max_queued_bytes < 1024
+        {
+            return Err(ConfigError::Validation(format!("max_queued_bytes must be at least 1024, got {}", self.max_queued_bytes)));
+        }
+
+        if self.max_messages_per_second == 0 || self.max_commands_per_tick == 0
+        {
+            return Err(ConfigError::Validation("max_messages_per_second and max_commands_per_tick must be at least 1".to_string()));
+        }
+
+        if self.max_bytes_per_second < 1024
+        {
+            return Err(ConfigError::Validation(format!("max_bytes_per_second must be at least 1024, got {}", self.max_bytes_per_second)));
+        }
+
+        if self.replay_keyframe_seconds == 0 || self.replay_max_file_megabytes == 0 || self.replay_keep_files == 0
+        {
+            return Err(ConfigError::Validation("replay_keyframe_seconds, replay_max_file_megabytes and replay_keep_files must be at least 1".to_string()));
+        }
+
+        if self.server_name.trim().len() == 0
+        {
+            return Err(ConfigError::Validation("server_name must not be empty".to_string()));
+        }
+
+        if self.level.trim().len() == 0
+        {
+            return Err(ConfigError::Validation("level must not be empty".to_string()));
+        }
+
+        rules_file::validate_rules(&self.rules).map_err(ConfigError::Validation)
+    }
+}
+
+impl FromStr for GameMode
+{
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<GameMode, ()>
+    {
+        match s
+        {
+            "deathmatch"      => Ok(GameMode::Deathmatch),
+            "team_deathmatch" => Ok(GameMode::TeamDeathmatch),
+            _ => Err(())
+        }
+    }
+}
+
+impl fmt::Display for GameMode
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            GameMode::Deathmatch     => write!(f, "deathmatch"),
+            GameMode::TeamDeathmatch => write!(f, "team_deathmatch"),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            ConfigError::HelpRequested(ref usage) => write!(f, "{}", usage),
+            ConfigError::Arguments(ref e) => write!(f, "Invalid arguments: {}", e),
+            ConfigError::File(ref path, ref e) => write!(f, "Failed to read config file {}: {}", path, e),
+            ConfigError::FileSyntax(ref path, ref e) => write!(f, "Failed to parse config file {}: {}", path, e),
+            ConfigError::InvalidValue(ref key, ref value, ref reason) => write!(f, "Invalid value '{}' for {}: {}", value, key, reason),
+            ConfigError::Validation(ref e) => write!(f, "Invalid configuration: {}", e),
+        }
+    }
+}
+
+fn parse_slow_consumer_policy(value: &str) -> Result<SlowConsumerPolicy, ()>
+{
+    match value
+    {
+        "resync"     => Ok(SlowConsumerPolicy::Resync),
+        "disconnect" => Ok(SlowConsumerPolicy::Disconnect),
+        _ => Err(())
+    }
+}
+
+fn command_line_options() -> Options
+{
+    let mut options = Options::new();
+    options.optflag("h", "help", "print this help");
+    options.optopt("c", "config", "path to a TOML config file", "PATH");
+
+    for &(key, description) in SETTINGS
+    {
+        options.optopt("", &option_name(key), description, "VALUE");
+    }
+
+    options
+}
+
+fn option_name(key: &str) -> String
+{
+    key.replace("_", "-")
+}
+
+fn value_to_string(value: &toml::Value) -> String
+{
+    match *value
+    {
+        toml::Value::String(ref s) => s.clone(),
+        ref other => other.to_string()
+    }
+}
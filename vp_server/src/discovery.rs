@@ -0,0 +1,74 @@
+use std::thread;
+use std::net::{UdpSocket, SocketAddr};
+
+use bincode::SizeLimit;
+use bincode::rustc_serialize::{encode, decode};
+
+use vp_shared::discovery::{DiscoveryQuery, DiscoveryResponse};
+
+use status::SharedStatus;
+
+const MAX_QUERY_SIZE: usize = 64;
+
+/// Answers on the same interface the game listens on, so a server bound to one network isn't
+/// announced on the others.
+pub fn spawn_discovery_responder(address: SocketAddr, game_port: u16, max_players: usize, status: SharedStatus)
+{
+    let socket = match UdpSocket::bind(address)
+    {
+        Ok(socket) => socket,
+        Err(e) =>
+        {
+            error!("Failed to bind LAN discovery address {}, {}", address, e);
+            return;
+        }
+    };
+
+    info!("Answering LAN discovery queries on {}", address);
+
+    thread::spawn(move ||
+    {
+        let mut buffer = [0; MAX_QUERY_SIZE];
+
+        loop
+        {
+            let (length, sender) = match socket.recv_from(&mut buffer)
+            {
+                Ok(received) => received,
+                Err(e) =>
+                {
+                    error!("Failed to receive discovery query, {}", e);
+                    continue;
+                }
+            };
+
+            match decode::<DiscoveryQuery>(&buffer[..length])
+            {
+                Ok(ref query) if query.is_valid() && query.is_compatible() => {},
+                Ok(ref query) if query.is_valid() =>
+                {
+                    debug!("Ignoring discovery query from {} with protocol version {}", sender, query.protocol_version);
+                    continue;
+                },
+                _ =>
+                {
+                    debug!("Ignoring invalid discovery query from {}", sender);
+                    continue;
+                }
+            }
+
+            let response =
+            {
+                let status = status.lock().unwrap();
+                DiscoveryResponse::new(status.name(), game_port, status.map(), status.player_count() as u32, max_players as u32)
+            };
+
+            let data = encode(&response, SizeLimit::Infinite).unwrap();
+            match socket.send_to(&data, sender)
+            {
+                Ok(_) => debug!("Answered discovery query from {}", sender),
+                Err(e) => error!("Failed to answer discovery query from {}, {}", sender, e)
+            }
+        }
+    });
+}
@@ -0,0 +1,322 @@
+use std::fmt;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::fs::{File, OpenOptions};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use vp_shared::RejectReason;
+
+/// An address or a CIDR range of addresses, e.g. `10.0.0.0/8`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IpRange
+{
+    address: IpAddr,
+    prefix_length: u32
+}
+
+/// Bans loaded from a file, new bans are appended to the same file so they survive restarts.
+pub struct BanList
+{
+    path: Option<String>,
+    ranges: Vec<IpRange>
+}
+
+pub struct AdmissionPolicy
+{
+    pub ban_list: BanList,
+    pub admin_addresses: Vec<IpRange>,
+    pub max_connections_per_ip: usize,
+    pub reserved_slots: usize
+}
+
+impl IpRange
+{
+    pub fn single(address: IpAddr) -> IpRange
+    {
+        IpRange { address: address, prefix_length: max_prefix_length(&address) }
+    }
+
+    pub fn contains(&self, address: &IpAddr) -> bool
+    {
+        match (self.address, *address)
+        {
+            (IpAddr::V4(range), IpAddr::V4(address)) => prefix_matches(&range.octets(), &address.octets(), self.prefix_length),
+            (IpAddr::V6(range), IpAddr::V6(address)) => prefix_matches(&ipv6_octets(&range), &ipv6_octets(&address), self.prefix_length),
+            _ => false
+        }
+    }
+}
+
+impl FromStr for IpRange
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<IpRange, String>
+    {
+        let mut parts = s.trim().splitn(2, '/');
+        let address_part = parts.next().unwrap_or("");
+
+        let address = match (Ipv4Addr::from_str(address_part), Ipv6Addr::from_str(address_part))
+        {
+            (Ok(address), _) => IpAddr::V4(address),
+            (_, Ok(address)) => IpAddr::V6(address),
+            _ => return Err(format!("'{}' is not an IP address", address_part))
+        };
+
+        let prefix_length = match parts.next()
+        {
+            Some(prefix) => try!(prefix.parse().map_err(|_| format!("'{}' is not a prefix length", prefix))),
+            None => max_prefix_length(&address)
+        };
+
+        if prefix_length > max_prefix_length(&address)
+        {
+            return Err(format!("prefix length {} is too long for {}", prefix_length, address_part));
+        }
+
+        Ok(IpRange { address: address, prefix_length: prefix_length })
+    }
+}
+
+impl fmt::Display for IpRange
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        if self.prefix_length == max_prefix_length(&self.address)
+        {
+            write!(f, "{}", self.address)
+        }
+        else
+        {
+            write!(f, "{}/{}", self.address, self.prefix_length)
+        }
+    }
+}
+
+impl BanList
+{
+    pub fn empty() -> BanList
+    {
+        BanList { path: None, ranges: Vec::new() }
+    }
+
+    /// A missing file is an empty list, it gets created by the first ban.
+    pub fn load(path: &str) -> Result<BanList, String>
+    {
+        let mut ranges = Vec::new();
+
+        match File::open(path)
+        {
+            Ok(file) =>
+            {
+                for (number, line) in BufReader::new(file).lines().enumerate()
+                {
+                    let line = try!(line.map_err(|e| format!("Failed to read ban list {}: {}", path, e)));
+                    let entry = line.split('#').next().unwrap_or("").trim();
+                    if entry.len() == 0
+                    {
+                        continue;
+                    }
+
+                    ranges.push(try!(entry.parse().map_err(|e| format!("Invalid ban in {} on line {}: {}", path, number + 1, e))));
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(format!("Failed to open ban list {}: {}", path, e))
+        }
+
+        info!("Loaded {} bans from {}", ranges.len(), path);
+        Ok(BanList { path: Some(path.to_string()), ranges: ranges })
+    }
+
+    pub fn is_banned(&self, address: &IpAddr) -> bool
+    {
+        self.ranges.iter().any(|range| range.contains(address))
+    }
+
+    pub fn ban(&mut self, range: IpRange) -> io::Result<()>
+    {
+        if self.ranges.contains(&range)
+        {
+            return Ok(());
+        }
+
+        self.ranges.push(range);
+
+        match self.path
+        {
+            Some(ref path) =>
+            {
+                let mut file = try!(OpenOptions::new().append(true).create(true).open(path));
+                writeln!(file, "{}", range)
+            },
+            None => Ok(())
+        }
+    }
+}
+
+impl AdmissionPolicy
+{
+    /// Admins get the reserved slots, everyone else has to fit into the rest.
+    pub fn check(&self, address: &IpAddr, connections: usize, connections_from_address: usize, max_clients: usize) -> Result<(), RejectReason>
+    {
+        if self.ban_list.is_banned(address)
+        {
+            return Err(RejectReason::Banned);
+        }
+
+        if connections >= max_clients
+        {
+            return Err(RejectReason::ServerFull);
+        }
+
+        if self.admin_addresses.iter().any(|range| range.contains(address))
+        {
+            return Ok(());
+        }
+
+        if connections >= max_clients - self.reserved_slots
+        {
+            return Err(RejectReason::ServerFull);
+        }
+
+        if connections_from_address >= self.max_connections_per_ip
+        {
+            return Err(RejectReason::TooManyConnections);
+        }
+
+        Ok(())
+    }
+}
+
+pub fn parse_ranges(value: &str) -> Result<Vec<IpRange>, String>
+{
+    value
+        .split(',')
+        .map(|range| range.trim())
+        .filter(|range| range.len() != 0)
+        .map(|range| range.parse())
+        .collect()
+}
+
+fn max_prefix_length(address: &IpAddr) -> u32
+{
+    match *address
+    {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128
+    }
+}
+
+fn ipv6_octets(address: &Ipv6Addr) -> Vec<u8>
+{
+    address.segments().iter().flat_map(|segment| vec![(segment >> 8) as u8, *segment as u8].into_iter()).collect()
+}
+
+fn prefix_matches(range: &[u8], address: &[u8], prefix_length: u32) -> bool
+{
+    for (i, (&range_byte, &address_byte)) in range.iter().zip(address.iter()).enumerate()
+    {
+        let bits = prefix_length.saturating_sub(i as u32 * 8);
+        if bits == 0
+        {
+            break;
+        }
+
+        let mask = if bits >= 8 { 0xff } else { 0xffu8 << (8 - bits) };
+        if range_byte & mask != address_byte & mask
+        {
+            return false;
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use vp_shared::RejectReason;
+    use super::*;
+
+    fn v4(a: u8, b: u8, c: u8, d: u8) -> IpAddr
+    {
+        IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+    }
+
+    fn range(s: &str) -> IpRange
+    {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_addresses_and_ranges()
+    {
+        assert_eq!(range("10.0.0.1"), IpRange::single(v4(10, 0, 0, 1)));
+        assert_eq!(range(" 10.0.0.0/8 ").to_string(), "10.0.0.0/8");
+        assert_eq!(range("10.0.0.1/32").to_string(), "10.0.0.1");
+        assert_eq!(range("0.0.0.0/0").to_string(), "0.0.0.0/0");
+        assert_eq!(range("2001:db8::/32").to_string(), "2001:db8::/32");
+        assert_eq!(range("::1").to_string(), "::1");
+    }
+
+    #[test]
+    fn rejects_malformed_ranges()
+    {
+        for s in ["", "10.0.0", "localhost", "10.0.0.0/", "10.0.0.0/x", "10.0.0.0/-1", "10.0.0.0/33", "::/129", "10.0.0.0/8/8"].iter()
+        {
+            assert!(s.parse::<IpRange>().is_err(), "'{}' should not parse", s);
+        }
+    }
+
+    #[test]
+    fn ranges_contain_the_addresses_under_their_prefix()
+    {
+        assert!(range("10.0.0.0/8").contains(&v4(10, 255, 1, 2)));
+        assert!(!range("10.0.0.0/8").contains(&v4(11, 0, 0, 1)));
+        assert!(range("192.168.1.128/25").contains(&v4(192, 168, 1, 200)));
+        assert!(!range("192.168.1.128/25").contains(&v4(192, 168, 1, 100)));
+        assert!(range("0.0.0.0/0").contains(&v4(1, 2, 3, 4)));
+        assert!(range("10.0.0.1").contains(&v4(10, 0, 0, 1)));
+        assert!(!range("10.0.0.1").contains(&v4(10, 0, 0, 2)));
+
+        let ipv6 = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+        assert!(range("2001:db8::/32").contains(&ipv6));
+        assert!(!range("2001:db9::/32").contains(&ipv6));
+        assert!(!range("0.0.0.0/0").contains(&ipv6));
+        assert!(!range("::/0").contains(&v4(10, 0, 0, 1)));
+    }
+
+    #[test]
+    fn parses_range_lists()
+    {
+        assert_eq!(parse_ranges("10.0.0.0/8, ,::1").unwrap(), vec![range("10.0.0.0/8"), range("::1")]);
+        assert!(parse_ranges("10.0.0.0/8,nonsense").is_err());
+    }
+
+    #[test]
+    fn admission_keeps_reserved_slots_for_admins()
+    {
+        let mut ban_list = BanList::empty();
+        ban_list.ban(range("192.168.0.0/16")).unwrap();
+        let policy = AdmissionPolicy
+        {
+            ban_list: ban_list,
+            admin_addresses: vec![range("127.0.0.1")],
+            max_connections_per_ip: 2,
+            reserved_slots: 1
+        };
+        let player = v4(10, 0, 0, 1);
+        let admin = v4(127, 0, 0, 1);
+
+        assert_eq!(policy.check(&player, 2, 0, 4), Ok(()));
+        assert_eq!(policy.check(&player, 3, 0, 4), Err(RejectReason::ServerFull));
+        assert_eq!(policy.check(&admin, 3, 0, 4), Ok(()));
+        assert_eq!(policy.check(&admin, 4, 0, 4), Err(RejectReason::ServerFull));
+        assert_eq!(policy.check(&player, 0, 2, 4), Err(RejectReason::TooManyConnections));
+        assert_eq!(policy.check(&v4(192, 168, 3, 4), 0, 0, 4), Err(RejectReason::Banned));
+    }
+}
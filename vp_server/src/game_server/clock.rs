@@ -0,0 +1,78 @@
+use std::thread;
+
+use time::{Duration, PreciseTime};
+
+pub trait Clock
+{
+    fn elapsed(&self) -> Duration;
+    fn sleep(&mut self, duration: Duration);
+}
+
+pub struct RealClock
+{
+    start_time: PreciseTime,
+    spin_wait: Duration
+}
+
+pub struct ManualClock
+{
+    elapsed: Duration
+}
+
+impl RealClock
+{
+    pub fn new(spin_wait: Duration) -> RealClock
+    {
+        RealClock { start_time: PreciseTime::now(), spin_wait: spin_wait }
+    }
+}
+
+impl Clock for RealClock
+{
+    fn elapsed(&self) -> Duration
+    {
+        self.start_time.to(PreciseTime::now())
+    }
+
+    fn sleep(&mut self, duration: Duration)
+    {
+        let sleep_start = self.elapsed();
+
+        let time_to_sleep = duration - self.spin_wait;
+        if time_to_sleep > Duration::zero()
+        {
+            thread::sleep(time_to_sleep.to_std().unwrap());
+        }
+
+        while self.elapsed() - sleep_start < duration
+        {
+            thread::yield_now();
+        }
+    }
+}
+
+impl ManualClock
+{
+    pub fn new() -> ManualClock
+    {
+        ManualClock { elapsed: Duration::zero() }
+    }
+
+    pub fn advance(&mut self, duration: Duration)
+    {
+        self.elapsed = self.elapsed + duration;
+    }
+}
+
+impl Clock for ManualClock
+{
+    fn elapsed(&self) -> Duration
+    {
+        self.elapsed
+    }
+
+    fn sleep(&mut self, duration: Duration)
+    {
+        self.advance(duration);
+    }
+}
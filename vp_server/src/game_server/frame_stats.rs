@@ -0,0 +1,162 @@
+use std::collections::VecDeque;
+
+use time::Duration;
+
+/// Sorting the window for percentiles is too much work to do every tick, they are refreshed this often.
+const PERCENTILE_INTERVAL_TICKS: u64 = 50;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameTiming
+{
+    pub processing: Duration,
+    pub sleep: Duration,
+    pub overrun: Duration
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Percentiles
+{
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameStatsSummary
+{
+    pub ticks: u64,
+    pub overruns: u64,
+    pub last: FrameTiming,
+    pub processing: Percentiles,
+    pub sleep: Percentiles,
+    pub overrun: Percentiles
+}
+
+pub struct FrameStats
+{
+    budget: Duration,
+    window_size: usize,
+    window: VecDeque<FrameTiming>,
+    ticks: u64,
+    overruns: u64,
+    summary: FrameStatsSummary
+}
+
+impl Percentiles
+{
+    fn zero() -> Percentiles
+    {
+        Percentiles { p50: Duration::zero(), p95: Duration::zero(), p99: Duration::zero() }
+    }
+}
+
+impl FrameTiming
+{
+    fn zero() -> FrameTiming
+    {
+        FrameTiming { processing: Duration::zero(), sleep: Duration::zero(), overrun: Duration::zero() }
+    }
+}
+
+impl FrameStats
+{
+    pub fn new(budget: Duration, window_size: usize) -> FrameStats
+    {
+        FrameStats
+        {
+            budget: budget,
+            window_size: window_size,
+            window: VecDeque::with_capacity(window_size),
+            ticks: 0,
+            overruns: 0,
+            summary: FrameStatsSummary
+            {
+                ticks: 0,
+                overruns: 0,
+                last: FrameTiming::zero(),
+                processing: Percentiles::zero(),
+                sleep: Percentiles::zero(),
+                overrun: Percentiles::zero()
+            }
+        }
+    }
+
+    pub fn record(&mut self, tick: u64, processing: Duration, sleep: Duration)
+    {
+        let overrun = if processing > self.budget { processing - self.budget } else { Duration::zero() };
+
+        if overrun > Duration::zero()
+        {
+            self.overruns += 1;
+            warn!
+            (
+                "Tick {} took {} us, exceeding the {} us budget by {} us",
+                tick,
+                processing.num_microseconds().unwrap(),
+                self.budget.num_microseconds().unwrap(),
+                overrun.num_microseconds().unwrap()
+            );
+        }
+
+        if self.window.len() == self.window_size
+        {
+            self.window.pop_front();
+        }
+
+        let timing = FrameTiming { processing: processing, sleep: sleep, overrun: overrun };
+        self.window.push_back(timing);
+        self.ticks += 1;
+
+        self.summary.ticks = self.ticks;
+        self.summary.overruns = self.overruns;
+        self.summary.last = timing;
+        if self.ticks % PERCENTILE_INTERVAL_TICKS == 1
+        {
+            self.refresh_percentiles();
+        }
+    }
+
+    /// Counters and the last frame are current, percentiles are at most `PERCENTILE_INTERVAL_TICKS` old.
+    pub fn summary(&self) -> FrameStatsSummary
+    {
+        self.summary
+    }
+
+    /// A summary with percentiles over the whole window as it is now.
+    pub fn current_summary(&mut self) -> FrameStatsSummary
+    {
+        self.refresh_percentiles();
+        self.summary
+    }
+
+    fn refresh_percentiles(&mut self)
+    {
+        self.summary.processing = self.percentiles(|timing| timing.processing);
+        self.summary.sleep = self.percentiles(|timing| timing.sleep);
+        self.summary.overrun = self.percentiles(|timing| timing.overrun);
+    }
+
+    fn percentiles<F>(&self, f: F) -> Percentiles
+        where F: Fn(&FrameTiming) -> Duration
+    {
+        let mut values: Vec<Duration> = self.window.iter().map(f).collect();
+        values.sort();
+
+        Percentiles
+        {
+            p50: percentile(&values, 50),
+            p95: percentile(&values, 95),
+            p99: percentile(&values, 99),
+        }
+    }
+}
+
+fn percentile(sorted_values: &[Duration], percent: usize) -> Duration
+{
+    if sorted_values.len() == 0
+    {
+        return Duration::zero();
+    }
+
+    sorted_values[(sorted_values.len() - 1) * percent / 100]
+}
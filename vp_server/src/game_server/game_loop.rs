@@ -1,61 +1,129 @@
-use std::sync::mpsc::Receiver;
-use std::thread;
-use std::cmp::max;
+use std::sync::mpsc::{channel, Sender, Receiver};
 use std::collections::HashSet;
 
-use time::{Duration, PreciseTime};
+use time::Duration;
 use mio::Sender as MioSender;
 
-use game_server::{GameServerCommand, Frame};
+use game_server::{GameServerCommand, Frame, LoopTiming};
+use game_server::clock::{Clock, RealClock};
+use game_server::frame_stats::{FrameStats, FrameStatsSummary};
 use game_server::network_loop::{NetworkEvent, ClientId, NetworkCommand};
+use admin::AdminCommand;
+
+const FRAME_STATS_WINDOW: usize = 500;
 
 pub struct GameLoop
 {
-    target_frame_time: Duration,
+    timing: LoopTiming,
     network_receiver: Receiver<NetworkEvent>,
-    network_sender: MioSender<NetworkCommand>,
+    network_sender: Option<MioSender<NetworkCommand>>,
+    admin_receiver: Receiver<AdminCommand>,
+    admin_sender: Sender<AdminCommand>,
     currently_connected_clients: HashSet<ClientId>,
+    tick: u64,
+    frame_stats: FrameStats,
 }
 
-struct TimeGuard
+struct TimeGuard<'a, C: Clock + 'a>
 {
-    previous_wake_time: PreciseTime,
-    next_target_elapsed: Duration,
-    target_elapsed: Duration,
+    clock: &'a mut C,
+    timing: LoopTiming,
+    previous_time: Duration,
+    accumulated: Duration,
+    last_sleep: Duration,
 }
 
 impl GameLoop
 {
-    pub fn new(target_frame_time: Duration, network_receiver: Receiver<NetworkEvent>, network_sender: MioSender<NetworkCommand>) -> GameLoop
+    pub fn new(timing: LoopTiming, network_receiver: Receiver<NetworkEvent>, network_sender: MioSender<NetworkCommand>) -> GameLoop
     {
+        let (admin_tx, admin_rx) = channel();
+
         GameLoop
         {
-            target_frame_time: target_frame_time,
+            timing: timing,
             network_receiver: network_receiver,
-            network_sender: network_sender,
-            currently_connected_clients: HashSet::new()
+            network_sender: Some(network_sender),
+            admin_receiver: admin_rx,
+            admin_sender: admin_tx,
+            currently_connected_clients: HashSet::new(),
+            tick: 0,
+            frame_stats: FrameStats::new(timing.target_frame_time, FRAME_STATS_WINDOW)
         }
     }
 
-    pub fn run<F>(&mut self, mut frame_processor: F)
+    pub fn headless(timing: LoopTiming) -> (GameLoop, Sender<NetworkEvent>)
+    {
+        let (messages_tx, messages_rx) = channel();
+        let (admin_tx, admin_rx) = channel();
+
+        let game_loop = GameLoop
+        {
+            timing: timing,
+            network_receiver: messages_rx,
+            network_sender: None,
+            admin_receiver: admin_rx,
+            admin_sender: admin_tx,
+            currently_connected_clients: HashSet::new(),
+            tick: 0,
+            frame_stats: FrameStats::new(timing.target_frame_time, FRAME_STATS_WINDOW)
+        };
+
+        (game_loop, messages_tx)
+    }
+
+    pub fn run<F>(&mut self, frame_processor: F)
         where F: FnMut(Frame) -> GameServerCommand
     {
-        let mut time_guard = TimeGuard::start(self.target_frame_time);
+        let mut clock = RealClock::new(self.timing.spin_wait);
+        self.run_with_clock(&mut clock, frame_processor);
+    }
+
+    pub fn run_with_clock<C, F>(&mut self, clock: &mut C, mut frame_processor: F)
+        where C: Clock, F: FnMut(Frame) -> GameServerCommand
+    {
+        let mut time_guard = TimeGuard::start(clock, self.timing);
 
         loop
         {
-            time_guard.wait_for_time();
+            let ticks = time_guard.wait_for_ticks();
+            let mut sleep_time = time_guard.last_sleep;
 
-            let frame = self.create_frame();
-
-            match frame_processor(frame)
+            for _ in 0..ticks
             {
-                GameServerCommand::Continue(per_client_data) => self.schedule_send(per_client_data),
-                GameServerCommand::Exit => return,
+                let frame = self.create_frame();
+
+                let processing_start = time_guard.now();
+                let command = frame_processor(frame);
+                let processing_time = time_guard.now() - processing_start;
+
+                self.frame_stats.record(self.tick, processing_time, sleep_time);
+                sleep_time = Duration::zero();
+
+                match command
+                {
+                    GameServerCommand::Continue(network_commands) => self.schedule(network_commands),
+                    GameServerCommand::Exit(network_commands) =>
+                    {
+                        self.schedule(network_commands);
+                        self.schedule(vec![NetworkCommand::Shutdown]);
+                        return;
+                    }
+                }
             }
         }
     }
 
+    pub fn stats(&mut self) -> FrameStatsSummary
+    {
+        self.frame_stats.current_summary()
+    }
+
+    pub fn admin_channel(&self) -> Sender<AdminCommand>
+    {
+        self.admin_sender.clone()
+    }
+
     fn create_frame(&mut self) -> Frame
     {
         let mut messages = Vec::new();
@@ -68,7 +136,7 @@ impl GameLoop
                 {
                     match message
                     {
-                        NetworkEvent::ClientConnected(client_id) => {self.currently_connected_clients.insert(client_id);},
+                        NetworkEvent::ClientConnected(client_id, _) => {self.currently_connected_clients.insert(client_id);},
                         NetworkEvent::ClientDisconnected(client_id) => {self.currently_connected_clients.remove(&client_id);},
                         _ => {}
                     };
@@ -78,43 +146,203 @@ impl GameLoop
             }
         }
 
+        let mut admin_commands = Vec::new();
+        while let Ok(admin_command) = self.admin_receiver.try_recv()
+        {
+            admin_commands.push(admin_command);
+        }
+
+        self.tick += 1;
+
         Frame
         {
+            tick: self.tick,
             messages: messages,
+            admin_commands: admin_commands,
             currently_connected_clients: self.currently_connected_clients.iter().cloned().collect(),
-            elapsed_seconds: self.target_frame_time.num_microseconds().unwrap() as f32 / 1_000_000.0,
+            elapsed_seconds: self.timing.target_frame_time.num_microseconds().unwrap() as f32 / 1_000_000.0,
+            stats: self.frame_stats.summary(),
         }
     }
 
-    fn schedule_send(&mut self, sends: Vec<(ClientId, Vec<u8>)>)
+    fn schedule(&mut self, network_commands: Vec<NetworkCommand>)
     {
-        self.network_sender.send(NetworkCommand::Send(sends)).unwrap();
+        if let Some(ref network_sender) = self.network_sender
+        {
+            for network_command in network_commands
+            {
+                network_sender.send(network_command).unwrap();
+            }
+        }
     }
 }
 
-impl TimeGuard
+impl<'a, C: Clock> TimeGuard<'a, C>
 {
-    fn start(target_elapsed: Duration) -> TimeGuard
+    fn start(clock: &'a mut C, timing: LoopTiming) -> TimeGuard<'a, C>
+    {
+        let start_time = clock.elapsed();
+        TimeGuard
+        {
+            clock: clock,
+            timing: timing,
+            previous_time: start_time,
+            accumulated: Duration::zero(),
+            last_sleep: Duration::zero()
+        }
+    }
+
+    fn now(&self) -> Duration
     {
-        let start_time = PreciseTime::now();
-        TimeGuard {previous_wake_time: start_time, target_elapsed: target_elapsed, next_target_elapsed: target_elapsed}
+        self.clock.elapsed()
     }
 
-    fn wait_for_time(&mut self)
+    fn wait_for_ticks(&mut self) -> u32
     {
-        let frame_time = PreciseTime::now();
-        let elapsed = self.previous_wake_time.to(frame_time);
-        let time_to_sleep = self.next_target_elapsed - elapsed;
+        self.accumulate();
 
-        if time_to_sleep > Duration::zero()
+        let target = self.timing.target_frame_time;
+        let sleep_start = self.now();
+        if self.accumulated < target
         {
-            thread::sleep_ms(time_to_sleep.num_milliseconds() as u32);
-            //TODO: spin wait last 2 ms?
+            self.clock.sleep(target - self.accumulated);
+            self.accumulate();
         }
+        self.last_sleep = self.now() - sleep_start;
 
-        let wake_time = PreciseTime::now();
-        let time_slept = frame_time.to(wake_time);
-        self.next_target_elapsed = max(Duration::zero(), self.target_elapsed + (time_to_sleep - time_slept));
-        self.previous_wake_time = wake_time;
+        let target_us = target.num_microseconds().unwrap();
+        let due_ticks = self.accumulated.num_microseconds().unwrap() / target_us;
+        let max_ticks = self.timing.max_catch_up_ticks as i64;
+
+        let ticks = if due_ticks > max_ticks
+        {
+            warn!("Game loop is {} ticks behind, skipping {} ticks", due_ticks, due_ticks - max_ticks);
+            self.accumulated = Duration::microseconds(self.accumulated.num_microseconds().unwrap() % target_us);
+            max_ticks
+        }
+        else
+        {
+            self.accumulated = self.accumulated - target * due_ticks as i32;
+            due_ticks
+        };
+
+        ticks as u32
+    }
+
+    fn accumulate(&mut self)
+    {
+        let now = self.clock.elapsed();
+        self.accumulated = self.accumulated + (now - self.previous_time);
+        self.previous_time = now;
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::str::FromStr;
+
+    use time::Duration;
+
+    use game_server::{GameServerCommand, LoopTiming};
+    use game_server::clock::{Clock, ManualClock};
+    use game_server::network_loop::NetworkEvent;
+    use super::GameLoop;
+
+    /// A manual clock that loses `stall` on one of its sleeps, like a server that got descheduled.
+    struct StallingClock
+    {
+        clock: ManualClock,
+        sleeps: u32,
+        stall_on_sleep: u32,
+        stall: Duration
+    }
+
+    impl Clock for StallingClock
+    {
+        fn elapsed(&self) -> Duration
+        {
+            self.clock.elapsed()
+        }
+
+        fn sleep(&mut self, duration: Duration)
+        {
+            self.sleeps += 1;
+            self.clock.sleep(duration);
+            if self.sleeps == self.stall_on_sleep
+            {
+                self.clock.advance(self.stall);
+            }
+        }
+    }
+
+    fn timing() -> LoopTiming
+    {
+        LoopTiming::new(Duration::milliseconds(20))
+    }
+
+    #[test]
+    fn runs_a_tick_per_frame_time()
+    {
+        let (mut game_loop, _network) = GameLoop::headless(timing());
+        let mut clock = ManualClock::new();
+        let mut ticks = Vec::new();
+
+        game_loop.run_with_clock(&mut clock, |frame|
+        {
+            assert_eq!(frame.elapsed_seconds, 0.02);
+            ticks.push(frame.tick);
+            if frame.tick == 10_000 { GameServerCommand::Exit(vec![]) } else { GameServerCommand::Continue(vec![]) }
+        });
+
+        assert_eq!(ticks, (1..10_001).collect::<Vec<u64>>());
+        assert_eq!(clock.elapsed(), Duration::milliseconds(20) * 10_000);
+        assert_eq!(game_loop.stats().ticks, 10_000);
+        assert_eq!(game_loop.stats().overruns, 0);
+    }
+
+    #[test]
+    fn catches_up_a_limited_number_of_ticks_after_a_stall()
+    {
+        let (mut game_loop, _network) = GameLoop::headless(timing());
+        let mut clock = StallingClock { clock: ManualClock::new(), sleeps: 0, stall_on_sleep: 10, stall: Duration::milliseconds(20) * 20 };
+        let mut ticks = 0;
+
+        game_loop.run_with_clock(&mut clock, |frame|
+        {
+            ticks += 1;
+            assert_eq!(frame.tick, ticks);
+            if frame.tick == 100 { GameServerCommand::Exit(vec![]) } else { GameServerCommand::Continue(vec![]) }
+        });
+
+        // 21 ticks were due after the stall, 5 ran and the other 16 were dropped
+        assert_eq!(clock.elapsed(), Duration::milliseconds(20) * 116);
+    }
+
+    #[test]
+    fn tracks_connected_clients()
+    {
+        let (mut game_loop, network) = GameLoop::headless(timing());
+        let mut clock = ManualClock::new();
+        network.send(NetworkEvent::ClientConnected(7, FromStr::from_str("127.0.0.1:9000").unwrap())).unwrap();
+
+        game_loop.run_with_clock(&mut clock, |frame|
+        {
+            match frame.tick
+            {
+                1 =>
+                {
+                    assert_eq!(frame.currently_connected_clients, vec![7]);
+                    assert_eq!(frame.get_clients_needing_snapshot::<Vec<usize>>(), vec![7]);
+                    network.send(NetworkEvent::ClientDisconnected(7)).unwrap();
+                    GameServerCommand::Continue(vec![])
+                },
+                _ =>
+                {
+                    assert_eq!(frame.currently_connected_clients, Vec::<usize>::new());
+                    GameServerCommand::Exit(vec![])
+                }
+            }
+        });
     }
 }
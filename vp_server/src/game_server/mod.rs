@@ -1,46 +1,78 @@
 pub mod network_loop;
-mod game_loop;
+pub mod clock;
+pub mod frame_stats;
+pub mod game_loop;
+pub mod rate_limit;
+pub mod admission;
 
-use std::net::SocketAddr;
 use std::sync::mpsc::channel;
 use std::iter::FromIterator;
 
 use time::Duration;
 
-use self::network_loop::NetworkLoop;
+use self::network_loop::{NetworkLoop, NetworkSettings};
+use self::admission::AdmissionPolicy;
 use self::game_loop::GameLoop;
-use game_server::network_loop::{NetworkEvent, ClientId};
+use self::frame_stats::FrameStatsSummary;
+use admin::AdminCommand;
+use metrics::SharedMetrics;
+use game_server::network_loop::{NetworkEvent, NetworkCommand, ClientId};
 
 pub struct Frame
 {
+    pub tick: u64,
     pub messages: Vec<NetworkEvent>,
+    pub admin_commands: Vec<AdminCommand>,
     pub currently_connected_clients: Vec<ClientId>,
-    pub elapsed_seconds: f32
+    pub elapsed_seconds: f32,
+    pub stats: FrameStatsSummary
+}
+
+#[derive(Clone, Copy)]
+pub struct LoopTiming
+{
+    pub target_frame_time: Duration,
+    pub max_catch_up_ticks: u32,
+    pub spin_wait: Duration
 }
 
 pub enum GameServerCommand
 {
-    Continue(Vec<(ClientId, Vec<u8>)>),
-    Exit
+    Continue(Vec<NetworkCommand>),
+    Exit(Vec<NetworkCommand>)
 }
 
-pub fn game_server(target_frame_time: Duration, address: SocketAddr, max_clients: usize) -> (GameLoop, NetworkLoop)
+pub fn game_server(timing: LoopTiming, network_settings: NetworkSettings, admission: AdmissionPolicy, metrics: SharedMetrics) -> (GameLoop, NetworkLoop)
 {
     let (messages_tx, messages_rx) = channel();
-    let network_loop = NetworkLoop::new(address, max_clients, messages_tx);
-    let game_loop = GameLoop::new(target_frame_time, messages_rx, network_loop.channel());
+    let network_loop = NetworkLoop::new(network_settings, admission, messages_tx, metrics);
+    let game_loop = GameLoop::new(timing, messages_rx, network_loop.channel());
 
     (game_loop, network_loop)
 }
 
+impl LoopTiming
+{
+    pub fn new(target_frame_time: Duration) -> LoopTiming
+    {
+        LoopTiming
+        {
+            target_frame_time: target_frame_time,
+            max_catch_up_ticks: 5,
+            spin_wait: Duration::milliseconds(2)
+        }
+    }
+}
+
 impl Frame
 {
-    pub fn get_just_connected_clients<T>(&self) -> T
+    pub fn get_clients_needing_snapshot<T>(&self) -> T
         where T: FromIterator<ClientId>
     {
         self.messages.iter().filter_map(|message| match message
         {
-            &NetworkEvent::ClientConnected(client_id) => Some(client_id),
+            &NetworkEvent::ClientConnected(client_id, _) => Some(client_id),
+            &NetworkEvent::ClientLagged(client_id) => Some(client_id),
             _ => None
         })
         .collect()
@@ -1,34 +1,94 @@
-use std::net::SocketAddr;
+use std::net::{SocketAddr, Shutdown};
 use std::io;
-use std::io::{Error, ErrorKind, Cursor, Read, Seek, SeekFrom};
+use std::mem;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
 use std::sync::mpsc::Sender;
-use std::collections::VecDeque;
+use std::collections::{VecDeque, HashMap};
 
+use time::Duration;
 use mio::{Token, EventLoop, EventSet, PollOpt, Handler, TryRead, TryWrite};
 use mio::util::Slab;
 use mio::tcp::{TcpListener, TcpStream};
 use mio::Sender as MioSender;
-use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
-
+use byteorder::{ByteOrder, ReadBytesExt, BigEndian};
+use bincode::SizeLimit;
+use bincode::rustc_serialize::encode_into;
+use nix;
+use nix::sys::uio::{writev, IoVec};
+
+use metrics::{SharedMetrics, DisconnectReason};
+use game_server::rate_limit::{RateLimiter, Verdict};
+use game_server::clock::{Clock, RealClock};
+use game_server::admission::{AdmissionPolicy, IpRange};
+use vp_shared::{Event, RejectReason};
+
+/// Identifies a connection for as long as the server runs. Slab tokens are reused once a client
+/// disconnects, so commands for a client that is already gone must not reach whoever got its slot.
 pub type ClientId = usize;
 
+/// A length prefixed message, shared by every client it is sent to.
+pub type Payload = Arc<Vec<u8>>;
+
+const SHUTDOWN_FLUSH_TIMEOUT_MS: u64 = 2000;
+const MAX_WRITE_BATCH: usize = 64;
+/// Player commands are tiny, a length prefix beyond this is garbage or an attempt to make the
+/// server buffer without bound.
+const MAX_MESSAGE_BYTES: usize = 16 * 1024;
+
 pub enum NetworkEvent
 {
-    ClientConnected(ClientId),
+    ClientConnected(ClientId, SocketAddr),
     ClientDisconnected(ClientId),
-    ClientDataReceived(ClientId, Vec<u8>)
+    ClientDataReceived(ClientId, Vec<u8>),
+    ClientLagged(ClientId)
 }
 
 pub enum NetworkCommand
 {
-    Send(Vec<(ClientId, Vec<u8>)>)
+    /// World state, a slow consumer under the resync policy may lose these to the snapshot.
+    Send(Vec<(ClientId, Payload)>),
+    /// Messages meant for a single client, which a snapshot does not replace.
+    SendPrivate(Vec<(ClientId, Payload)>),
+    Disconnect(ClientId),
+    Ban(ClientId),
+    BanRange(IpRange),
+    Shutdown
+}
+
+/// What a timer scheduled on the event loop was for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum NetworkTimeout
+{
+    /// Send queues were not flushed in time after a shutdown command.
+    ShutdownDeadline
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlowConsumerPolicy
+{
+    Resync,
+    Disconnect
+}
+
+#[derive(Clone, Copy)]
+pub struct NetworkSettings
+{
+    pub address: SocketAddr,
+    pub max_clients: usize,
+    pub max_queued_bytes: usize,
+    pub slow_consumer_policy: SlowConsumerPolicy,
+    pub max_messages_per_second: u32,
+    pub max_bytes_per_second: u32
 }
 
 pub struct NetworkLoop
 {
-    address: SocketAddr,
-    max_clients: usize,
+    settings: NetworkSettings,
+    admission: AdmissionPolicy,
     network_sender: Sender<NetworkEvent>,
+    metrics: SharedMetrics,
     event_loop: EventLoop<NetworkHandler>,
 }
 
@@ -36,27 +96,48 @@ struct NetworkHandler
 {
     listener_token: Token,
     listener: TcpListener,
+    settings: NetworkSettings,
+    admission: AdmissionPolicy,
     client_connections: Slab<ClientConnection>,
+    client_tokens: HashMap<ClientId, Token>,
+    next_client_id: ClientId,
     sender: Sender<NetworkEvent>,
+    metrics: SharedMetrics,
+    shutting_down: bool,
+    /// Times the rate limits.
+    clock: RealClock
 }
 
 struct ClientConnection
 {
     stream: TcpStream,
+    address: SocketAddr,
     token: Token,
-    send_queue: VecDeque<Vec<u8>>,
-    read_buffer: Vec<u8>
+    client_id: ClientId,
+    send_queue: VecDeque<QueuedPayload>,
+    send_offset: usize,
+    queued_bytes: usize,
+    read_buffer: Vec<u8>,
+    rate_limiter: RateLimiter
+}
+
+struct QueuedPayload
+{
+    payload: Payload,
+    /// Whether resyncing the client makes the payload redundant.
+    replaceable: bool
 }
 
 impl NetworkLoop
 {
-    pub fn new(address: SocketAddr, max_clients: usize, sender: Sender<NetworkEvent>) -> NetworkLoop
+    pub fn new(settings: NetworkSettings, admission: AdmissionPolicy, sender: Sender<NetworkEvent>, metrics: SharedMetrics) -> NetworkLoop
     {
         NetworkLoop
         {
-            address: address,
-            max_clients: max_clients,
+            settings: settings,
+            admission: admission,
             network_sender: sender,
+            metrics: metrics,
             event_loop: EventLoop::new().ok().expect("Failed to create event loop")
         }
     }
@@ -68,18 +149,31 @@ impl NetworkLoop
 
     pub fn run(mut self)
     {
-        NetworkHandler::bind(self.address, self.max_clients, self.network_sender).run(&mut self.event_loop);
+        NetworkHandler::bind(self.settings, self.admission, self.network_sender, self.metrics).run(&mut self.event_loop);
     }
 }
 
 impl NetworkHandler
 {
-    fn bind(address: SocketAddr, max_clients: usize, sender: Sender<NetworkEvent>) -> NetworkHandler
+    fn bind(settings: NetworkSettings, admission: AdmissionPolicy, sender: Sender<NetworkEvent>, metrics: SharedMetrics) -> NetworkHandler
     {
-        let listener = TcpListener::bind(&address).ok().expect("Failed to bind address");
+        let listener = TcpListener::bind(&settings.address).ok().expect("Failed to bind address");
         let listener_token = Token(1);
-        let slab = Slab::new_starting_at(Token(2), max_clients);
-        NetworkHandler { listener_token: listener_token, listener: listener, client_connections: slab, sender: sender }
+        let slab = Slab::new_starting_at(Token(2), settings.max_clients);
+        NetworkHandler
+        {
+            listener_token: listener_token,
+            listener: listener,
+            settings: settings,
+            admission: admission,
+            client_connections: slab,
+            client_tokens: HashMap::new(),
+            next_client_id: 1,
+            sender: sender,
+            metrics: metrics,
+            shutting_down: false,
+            clock: RealClock::new(Duration::zero())
+        }
     }
 
     fn run(&mut self, event_loop: &mut EventLoop<NetworkHandler>)
@@ -122,12 +216,12 @@ impl NetworkHandler
         if events.is_hup()
         {
             debug!("Hup event for {:?}", token);
-            self.disconnect_client(token);
+            self.disconnect_client(token, DisconnectReason::Hangup);
         }
         else if events.is_error()
         {
             debug!("Error event for {:?}", token);
-            self.disconnect_client(token);
+            self.disconnect_client(token, DisconnectReason::SocketError);
         }
         else
         {
@@ -135,32 +229,88 @@ impl NetworkHandler
             {
                 match self.find_connection(token).read(event_loop)
                 {
-                    Ok(messages) =>
+                    Ok((messages, bytes_read)) =>
                     {
-                        for message in messages.into_iter()
+                        self.metrics.lock().unwrap().record_received(bytes_read, messages.len());
+                        if self.charge_bytes(token, bytes_read, messages.len())
                         {
-                            self.sender.send(NetworkEvent::ClientDataReceived(token.0, message)).unwrap()
+                            self.forward_messages(token, messages);
                         }
                     },
+                    Err(ref e) if e.kind() == io::ErrorKind::InvalidData =>
+                    {
+                        warn!("Disconnecting {:?} from {}, {}", token, self.find_connection(token).address, e);
+                        self.disconnect_client(token, DisconnectReason::OversizedMessage);
+                    },
                     Err(e) =>
                     {
                         error!("Failed to read buffer for token {:?}, error: {}", token, e);
-                        self.disconnect_client(token);
+                        self.disconnect_client(token, DisconnectReason::ReadFailed);
                     }
                 }
             }
 
-            if events.is_writable()
+            if events.is_writable() && self.client_connections.contains(token)
             {
                 match self.find_connection(token).write(event_loop)
                 {
-                    Ok(_) => {},
+                    Ok(bytes_written) => self.metrics.lock().unwrap().record_written(bytes_written),
                     Err(e) =>
                     {
                         error!("Failed to write buffer for token {:?}, error: {}", token, e);
-                        self.disconnect_client(token);
+                        self.disconnect_client(token, DisconnectReason::WriteFailed);
                     }
                 }
+
+                self.shutdown_if_flushed(event_loop);
+            }
+        }
+    }
+
+    /// Returns whether the messages of the read may be forwarded.
+    fn charge_bytes(&mut self, token: Token, bytes_read: usize, message_count: usize) -> bool
+    {
+        let now = self.clock.elapsed();
+        match self.find_connection(token).rate_limiter.charge_bytes(bytes_read, now)
+        {
+            Verdict::Accept => return true,
+            Verdict::Drop => {},
+            Verdict::Warn => warn!("{:?} from {} exceeds its byte rate limit, dropping messages", token, self.find_connection(token).address),
+            Verdict::Kick =>
+            {
+                warn!("Kicking {:?} from {} for flooding", token, self.find_connection(token).address);
+                self.metrics.lock().unwrap().record_rate_limited(message_count);
+                self.disconnect_client(token, DisconnectReason::Flooding);
+                return false;
+            }
+        }
+
+        self.metrics.lock().unwrap().record_rate_limited(message_count);
+        false
+    }
+
+    fn forward_messages(&mut self, token: Token, messages: Vec<Vec<u8>>)
+    {
+        let client_id = self.find_connection(token).client_id;
+        for message in messages.into_iter()
+        {
+            let now = self.clock.elapsed();
+            match self.find_connection(token).rate_limiter.check_message(now)
+            {
+                Verdict::Accept => self.send_event(NetworkEvent::ClientDataReceived(client_id, message)),
+                Verdict::Drop => self.metrics.lock().unwrap().record_rate_limited(1),
+                Verdict::Warn =>
+                {
+                    warn!("{:?} from {} exceeds its rate limit, dropping messages", token, self.find_connection(token).address);
+                    self.metrics.lock().unwrap().record_rate_limited(1);
+                },
+                Verdict::Kick =>
+                {
+                    warn!("Kicking {:?} from {} for flooding", token, self.find_connection(token).address);
+                    self.metrics.lock().unwrap().record_rate_limited(1);
+                    self.disconnect_client(token, DisconnectReason::Flooding);
+                    return;
+                }
             }
         }
     }
@@ -169,24 +319,150 @@ impl NetworkHandler
     {
         match msg
         {
-            NetworkCommand::Send(sends) => self.process_send_command(event_loop, sends)
+            NetworkCommand::Send(sends) => self.process_send_command(event_loop, sends, true),
+            NetworkCommand::SendPrivate(sends) => self.process_send_command(event_loop, sends, false),
+            NetworkCommand::Disconnect(client_id) => self.process_disconnect_command(client_id),
+            NetworkCommand::Ban(client_id) => self.process_ban_command(client_id),
+            NetworkCommand::BanRange(range) => self.ban_range(range),
+            NetworkCommand::Shutdown => self.process_shutdown_command(event_loop)
         }
     }
 
-    fn process_send_command(&mut self, event_loop: &mut EventLoop<NetworkHandler>, sends: Vec<(ClientId, Vec<u8>)>)
+    fn process_disconnect_command(&mut self, client_id: ClientId)
     {
-        for (client_id, data) in sends
+        if let Some(token) = self.find_token(client_id)
         {
-            let token = Token(client_id);
+            self.disconnect_client(token, DisconnectReason::Kicked);
+        }
+    }
 
-            match self.find_connection(token).enqueue_data(event_loop, data)
+    fn process_ban_command(&mut self, client_id: ClientId)
+    {
+        let address = match self.find_token(client_id)
+        {
+            Some(token) => self.find_connection(token).address,
+            None =>
             {
-                Ok(_) => {},
+                error!("Failed to ban client {}, no such connection", client_id);
+                return;
+            }
+        };
+
+        self.ban_range(IpRange::single(address.ip()));
+    }
+
+    fn ban_range(&mut self, range: IpRange)
+    {
+        info!("Banning {}", range);
+        match self.admission.ban_list.ban(range)
+        {
+            Ok(_) => {},
+            Err(e) => error!("Failed to save ban of {}, it only lasts until restart, {}", range, e)
+        }
+
+        let banned_tokens: Vec<Token> = self.client_connections
+            .iter()
+            .filter(|connection| range.contains(&connection.address.ip()))
+            .map(|connection| connection.token)
+            .collect();
+
+        for token in banned_tokens
+        {
+            self.disconnect_client(token, DisconnectReason::Banned);
+        }
+    }
+
+    fn process_shutdown_command(&mut self, event_loop: &mut EventLoop<NetworkHandler>)
+    {
+        info!("Flushing send queues before shutdown...");
+        self.shutting_down = true;
+
+        match event_loop.timeout_ms(NetworkTimeout::ShutdownDeadline, SHUTDOWN_FLUSH_TIMEOUT_MS)
+        {
+            Ok(_) => {},
+            Err(e) => { error!("Failed to schedule shutdown timeout, {:?}", e); }
+        }
+
+        self.shutdown_if_flushed(event_loop);
+    }
+
+    fn shutdown_if_flushed(&mut self, event_loop: &mut EventLoop<NetworkHandler>)
+    {
+        if self.shutting_down && self.client_connections.iter().all(|connection| connection.send_queue.len() == 0)
+        {
+            info!("Send queues flushed, stopping network loop");
+            event_loop.shutdown();
+        }
+    }
+
+    fn process_send_command(&mut self, event_loop: &mut EventLoop<NetworkHandler>, sends: Vec<(ClientId, Payload)>, replaceable: bool)
+    {
+        self.metrics.lock().unwrap().record_enqueued(sends.len());
+
+        for (client_id, payload) in sends
+        {
+            let token = match self.find_token(client_id)
+            {
+                Some(token) => token,
+                None =>
+                {
+                    // the client disconnected before the game loop learned about it
+                    continue;
+                }
+            };
+
+            let result = self.find_connection(token).enqueue_payload(event_loop, QueuedPayload { payload: payload, replaceable: replaceable });
+
+            match result
+            {
+                Ok(_) => self.enforce_send_queue_limit(token),
                 Err(e) =>
                 {
                     error!("Failed to enqueue data for token {:?}, error: {}", token, e);
-                    self.disconnect_client(token);
+                    self.disconnect_client(token, DisconnectReason::WriteFailed);
+                }
+            }
+        }
+
+        let send_queue_depths = self.client_connections
+            .iter()
+            .map(|connection| (connection.client_id, connection.send_queue.len(), connection.queued_bytes))
+            .collect();
+        self.metrics.lock().unwrap().set_send_queue_depths(send_queue_depths);
+    }
+
+    fn enforce_send_queue_limit(&mut self, token: Token)
+    {
+        let queued_bytes = self.find_connection(token).queued_bytes;
+        if queued_bytes <= self.settings.max_queued_bytes
+        {
+            return;
+        }
+
+        match self.settings.slow_consumer_policy
+        {
+            SlowConsumerPolicy::Disconnect =>
+            {
+                warn!("Disconnecting slow consumer {:?} with {} bytes queued", token, queued_bytes);
+                self.disconnect_client(token, DisconnectReason::SlowConsumer);
+            },
+            SlowConsumerPolicy::Resync =>
+            {
+                let dropped = self.find_connection(token).drop_unsent();
+                self.metrics.lock().unwrap().record_dropped(dropped);
+
+                // private messages can't be dropped, a resync would not drain them and come around every tick
+                let queued_bytes = self.find_connection(token).queued_bytes;
+                if queued_bytes > self.settings.max_queued_bytes
+                {
+                    warn!("Disconnecting slow consumer {:?} with {} bytes queued that a resync doesn't replace", token, queued_bytes);
+                    self.disconnect_client(token, DisconnectReason::SlowConsumer);
+                    return;
                 }
+
+                warn!("Dropped {} stale messages for slow consumer {:?}, requesting resync", dropped, token);
+                let client_id = self.find_connection(token).client_id;
+                self.send_event(NetworkEvent::ClientLagged(client_id));
             }
         }
     }
@@ -205,14 +481,45 @@ impl NetworkHandler
 
     fn process_new_client_stream(&mut self, new_stream: TcpStream, event_loop: &mut EventLoop<NetworkHandler>)
     {
-        match self.client_connections.insert_with(|token| ClientConnection::new(new_stream, token))
+        let address = match new_stream.peer_addr()
+        {
+            Ok(address) => address,
+            Err(e) =>
+            {
+                error!("Failed to get address of new client, {}", e);
+                return;
+            }
+        };
+
+        let connections_from_address = self.client_connections
+            .iter()
+            .filter(|connection| connection.address.ip() == address.ip())
+            .count();
+
+        match self.admission.check(&address.ip(), self.client_connections.count(), connections_from_address, self.settings.max_clients)
+        {
+            Ok(_) => {},
+            Err(reason) =>
+            {
+                info!("Rejected connection from {}, {:?}", address, reason);
+                self.metrics.lock().unwrap().record_rejected(reason);
+                reject_client(new_stream, reason);
+                return;
+            }
+        }
+
+        let rate_limiter = RateLimiter::new(self.settings.max_messages_per_second, self.settings.max_bytes_per_second, self.clock.elapsed());
+        let client_id = self.next_client_id;
+        match self.client_connections.insert_with(|token| ClientConnection::new(new_stream, address, token, client_id, rate_limiter))
         {
             Some(token) => match self.find_connection(token).register(event_loop)
             {
                 Ok(_) =>
                 {
-                    debug!("New client {:?} registered with event loop", token);
-                    self.sender.send(NetworkEvent::ClientConnected(token.0)).unwrap();
+                    debug!("New client {} on {:?} from {} registered with event loop", client_id, token, address);
+                    self.next_client_id += 1;
+                    self.client_tokens.insert(client_id, token);
+                    self.send_event(NetworkEvent::ClientConnected(client_id, address));
                 },
                 Err(e) =>
                 {
@@ -240,10 +547,28 @@ impl NetworkHandler
         });
     }
 
-    fn disconnect_client(&mut self, token: Token)
+    fn disconnect_client(&mut self, token: Token, reason: DisconnectReason)
+    {
+        if let Some(connection) = self.client_connections.remove(token)
+        {
+            self.client_tokens.remove(&connection.client_id);
+            self.metrics.lock().unwrap().record_disconnect(reason);
+            self.send_event(NetworkEvent::ClientDisconnected(connection.client_id));
+        }
+    }
+
+    fn find_token(&self, client_id: ClientId) -> Option<Token>
+    {
+        self.client_tokens.get(&client_id).cloned()
+    }
+
+    fn send_event(&self, event: NetworkEvent)
     {
-        self.client_connections.remove(token);
-        self.sender.send(NetworkEvent::ClientDisconnected(token.0)).unwrap();
+        // the game loop is gone once shutdown starts
+        if !self.shutting_down
+        {
+            self.sender.send(event).unwrap();
+        }
     }
 
     fn find_connection<'a>(&'a mut self, token: Token) -> &'a mut ClientConnection
@@ -254,7 +579,7 @@ impl NetworkHandler
 
 impl Handler for NetworkHandler
 {
-    type Timeout = ();
+    type Timeout = NetworkTimeout;
     type Message = NetworkCommand;
 
     fn ready(&mut self, event_loop: &mut EventLoop<NetworkHandler>, token: Token, events: EventSet)
@@ -273,18 +598,35 @@ impl Handler for NetworkHandler
     {
         self.process_command(event_loop, msg);
     }
+
+    fn timeout(&mut self, event_loop: &mut EventLoop<NetworkHandler>, timeout: NetworkTimeout)
+    {
+        match timeout
+        {
+            NetworkTimeout::ShutdownDeadline =>
+            {
+                warn!("Timed out flushing send queues, stopping network loop");
+                event_loop.shutdown();
+            }
+        }
+    }
 }
 
 impl ClientConnection
 {
-    fn new(stream: TcpStream, token: Token) -> ClientConnection
+    fn new(stream: TcpStream, address: SocketAddr, token: Token, client_id: ClientId, rate_limiter: RateLimiter) -> ClientConnection
     {
         ClientConnection
         {
             stream: stream,
+            address: address,
             token: token,
+            client_id: client_id,
             send_queue: VecDeque::new(),
-            read_buffer: Vec::new()
+            send_offset: 0,
+            queued_bytes: 0,
+            read_buffer: Vec::new(),
+            rate_limiter: rate_limiter
         }
     }
 
@@ -316,9 +658,9 @@ impl ClientConnection
         )
     }
 
-    fn read(&mut self, event_loop: &mut EventLoop<NetworkHandler>) -> io::Result<Vec<Vec<u8>>>
+    fn read(&mut self, event_loop: &mut EventLoop<NetworkHandler>) -> io::Result<(Vec<Vec<u8>>, usize)>
     {
-        try!(self.stream.try_read_buf(&mut self.read_buffer));
+        let bytes_read = try!(self.stream.try_read_buf(&mut self.read_buffer)).unwrap_or(0);
 
         let (messages, remainder) = try!(self.read_messages());
 
@@ -327,7 +669,7 @@ impl ClientConnection
 
         try!(self.reregister(event_loop));
 
-        Ok(messages)
+        Ok((messages, bytes_read))
     }
 
     fn read_messages(&mut self) -> io::Result<(Vec<Vec<u8>>, Vec<u8>)>
@@ -338,12 +680,18 @@ impl ClientConnection
 
         loop
         {
-            if cursor.position() as usize > self.read_buffer.len() - 4
+            if self.read_buffer.len() < cursor.position() as usize + 4
             {
                 break;
             }
 
             let length = try!(cursor.read_u32::<BigEndian>()) as usize;
+            if length > MAX_MESSAGE_BYTES
+            {
+                // the remainder never shrinks below an incomplete message, so this bounds the read buffer
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("message of {} bytes exceeds the limit of {}", length, MAX_MESSAGE_BYTES)));
+            }
+
             if (self.read_buffer.len() - cursor.position() as usize) < length
             {
                 cursor.seek(SeekFrom::Current(-4)).unwrap();
@@ -361,41 +709,245 @@ impl ClientConnection
         Ok((messages, remainder))
     }
 
-    fn enqueue_data(&mut self, event_loop: &mut EventLoop<NetworkHandler>, data: Vec<u8>) -> io::Result<()>
+    fn enqueue_payload(&mut self, event_loop: &mut EventLoop<NetworkHandler>, queued: QueuedPayload) -> io::Result<()>
     {
-        let mut length_arr = Vec::with_capacity(4);
-        length_arr.write_u32::<BigEndian>(data.len() as u32).unwrap();
-        self.send_queue.push_back(length_arr);
-        self.send_queue.push_back(data);
+        self.queued_bytes += queued.payload.len();
+        self.send_queue.push_back(queued);
         self.reregister(event_loop)
     }
 
-    fn write(&mut self, event_loop: &mut EventLoop<NetworkHandler>) -> io::Result<()>
+    /// Drops the queued world state the resync snapshot replaces, private messages stay queued.
+    fn drop_unsent(&mut self) -> usize
     {
-        self.send_queue.pop_front()
-            .ok_or(Error::new(ErrorKind::Other, "Could not pop send queue"))
-            .and_then(|data|
+        let (dropped, dropped_bytes) = drop_replaceable(&mut self.send_queue, self.send_offset);
+        self.queued_bytes -= dropped_bytes;
+        dropped
+    }
+
+    fn write(&mut self, event_loop: &mut EventLoop<NetworkHandler>) -> io::Result<usize>
+    {
+        let mut total_written = 0;
+
+        while self.send_queue.len() != 0
+        {
+            let written = match try!(self.write_batch())
             {
-                match self.stream.try_write(&data)
-                {
-                    Ok(None) =>
-                    {
-                        //would block
-                        self.send_queue.push_front(data);
-                        Ok(())
-                    },
-                    Ok(Some(n)) =>
-                    {
-                        if n < data.len()
-                        {
-                            let remainder = data.into_iter().skip(n).collect();
-                            self.send_queue.push_front(remainder);
-                        }
-                        Ok(())
-                    },
-                    Err(e) => Err(e)
-                }
-            })
-            .and_then(|_| self.reregister(event_loop))
+                Some(n) if n > 0 => n,
+                _ => break //would block
+            };
+
+            total_written += written;
+            self.queued_bytes -= written;
+            self.consume_written(written);
+        }
+
+        try!(self.reregister(event_loop));
+
+        Ok(total_written)
+    }
+
+    fn write_batch(&mut self) -> io::Result<Option<usize>>
+    {
+        // gather the front of the queue into a single writev call
+        let buffers: Vec<IoVec<&[u8]>> = unsent_slices(&self.send_queue, self.send_offset)
+            .into_iter()
+            .map(|slice| IoVec::from_slice(slice))
+            .collect();
+
+        loop
+        {
+            match writev(self.stream.as_raw_fd(), &buffers)
+            {
+                Ok(n) => return Ok(Some(n)),
+                // a signal arrived before anything was written, the shutdown handler makes this routine
+                Err(nix::Error::Sys(nix::errno::EINTR)) => {},
+                Err(nix::Error::Sys(nix::errno::EAGAIN)) => return Ok(None),
+                Err(nix::Error::Sys(errno)) => return Err(io::Error::from_raw_os_error(errno as i32)),
+                Err(e) => return Err(io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))
+            }
+        }
+    }
+
+    fn consume_written(&mut self, mut written: usize)
+    {
+        while written > 0
+        {
+            let remaining = self.send_queue[0].payload.len() - self.send_offset;
+            if written < remaining
+            {
+                self.send_offset += written;
+                return;
+            }
+
+            written -= remaining;
+            self.send_queue.pop_front();
+            self.send_offset = 0;
+        }
+    }
+}
+
+/// The unsent part of the front of the queue, borrowed from the shared payloads rather than copied.
+fn unsent_slices<'a>(send_queue: &'a VecDeque<QueuedPayload>, send_offset: usize) -> Vec<&'a [u8]>
+{
+    send_queue
+        .iter()
+        .take(MAX_WRITE_BATCH)
+        .enumerate()
+        .map(|(i, queued)| if i == 0 { &queued.payload[send_offset..] } else { &queued.payload[..] })
+        .collect()
+}
+
+/// Removes the payloads a resync replaces, returns how many were dropped and their bytes.
+fn drop_replaceable(send_queue: &mut VecDeque<QueuedPayload>, send_offset: usize) -> (usize, usize)
+{
+    let queue = mem::replace(send_queue, VecDeque::new());
+    let mut dropped = 0;
+    let mut dropped_bytes = 0;
+
+    for (i, queued) in queue.into_iter().enumerate()
+    {
+        // a partially written message has to be completed, otherwise the stream framing breaks
+        if !queued.replaceable || (i == 0 && send_offset > 0)
+        {
+            send_queue.push_back(queued);
+        }
+        else
+        {
+            dropped += 1;
+            dropped_bytes += queued.payload.len();
+        }
+    }
+
+    (dropped, dropped_bytes)
+}
+
+fn reject_client(mut stream: TcpStream, reason: RejectReason)
+{
+    let payload = frame_payload(|buffer| encode_into(&vec![Event::ConnectionRejected(reason)], buffer, SizeLimit::Infinite).unwrap());
+
+    // best effort, a fresh socket has plenty of room in its send buffer
+    match stream.try_write(&payload)
+    {
+        Ok(_) => {},
+        Err(e) => debug!("Failed to send rejection, {}", e)
+    }
+
+    // closing with a plain drop may reset the connection over unread data and lose the rejection,
+    // shutting down the sending side first lets it go out ahead of the close
+    if let Err(e) = stream.shutdown(Shutdown::Write)
+    {
+        debug!("Failed to shut down rejected connection, {}", e);
+    }
+}
+
+/// Frames a message once, so the same buffer can be queued for any number of clients.
+pub fn frame_payload<F>(write_message: F) -> Payload
+    where F: FnOnce(&mut Vec<u8>)
+{
+    let mut buffer = vec![0; 4];
+    write_message(&mut buffer);
+
+    let length = (buffer.len() - 4) as u32;
+    BigEndian::write_u32(&mut buffer[..4], length);
+
+    Arc::new(buffer)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::io::Read;
+    use std::net;
+    use std::os::unix::io::AsRawFd;
+    use std::collections::VecDeque;
+
+    use byteorder::{ByteOrder, BigEndian};
+    use nix::sys::uio::{writev, IoVec};
+
+    use super::{QueuedPayload, Payload, MAX_WRITE_BATCH, frame_payload, unsent_slices, drop_replaceable};
+
+    fn queue_of(payloads: &[Payload]) -> VecDeque<QueuedPayload>
+    {
+        payloads.iter().map(|payload| QueuedPayload { payload: payload.clone(), replaceable: true }).collect()
+    }
+
+    #[test]
+    fn payloads_are_framed_with_their_length()
+    {
+        let payload = frame_payload(|buffer| buffer.extend([1u8, 2, 3].iter().cloned()));
+        assert_eq!(payload.len(), 7);
+        assert_eq!(BigEndian::read_u32(&payload[..4]), 3);
+        assert_eq!(&payload[4..], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn queued_broadcasts_share_one_buffer()
+    {
+        let broadcast = frame_payload(|buffer| buffer.extend([7u8; 100].iter().cloned()));
+        let clients: Vec<VecDeque<QueuedPayload>> = (0..8).map(|_| queue_of(&[broadcast.clone()])).collect();
+
+        // every client's write is gathered straight from the broadcast buffer
+        for queue in clients.iter()
+        {
+            let slices = unsent_slices(queue, 0);
+            assert_eq!(slices.len(), 1);
+            assert_eq!(slices[0].as_ptr(), broadcast.as_ptr());
+            assert_eq!(slices[0].len(), broadcast.len());
+        }
+
+        let partially_sent = unsent_slices(&clients[0], 10);
+        assert_eq!(partially_sent[0].as_ptr(), broadcast[10..].as_ptr());
+    }
+
+    #[test]
+    fn writes_are_batched()
+    {
+        let payloads: Vec<Payload> = (0..MAX_WRITE_BATCH + 10).map(|i| frame_payload(|buffer| buffer.push(i as u8))).collect();
+        assert_eq!(unsent_slices(&queue_of(&payloads), 0).len(), MAX_WRITE_BATCH);
+    }
+
+    #[test]
+    fn gathered_payloads_arrive_in_order()
+    {
+        let first = frame_payload(|buffer| buffer.extend([1u8, 2, 3].iter().cloned()));
+        let second = frame_payload(|buffer| buffer.extend([4u8, 5].iter().cloned()));
+        let queue = queue_of(&[first.clone(), second.clone()]);
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let sender = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (receiver, _) = listener.accept().unwrap();
+
+        // resume after the first two bytes, as after a short write
+        let buffers: Vec<IoVec<&[u8]>> = unsent_slices(&queue, 2).into_iter().map(|slice| IoVec::from_slice(slice)).collect();
+        let written = writev(sender.as_raw_fd(), &buffers).unwrap();
+        assert_eq!(written, first.len() - 2 + second.len());
+
+        let mut received = Vec::new();
+        receiver.take(written as u64).read_to_end(&mut received).unwrap();
+
+        let mut expected = first[2..].to_vec();
+        expected.extend(second.iter().cloned());
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn resyncs_only_drop_what_the_snapshot_replaces()
+    {
+        let state = frame_payload(|buffer| buffer.extend([1u8; 10].iter().cloned()));
+        let private = frame_payload(|buffer| buffer.extend([2u8; 20].iter().cloned()));
+        let mut queue = queue_of(&[state.clone(), state.clone()]);
+        queue.push_back(QueuedPayload { payload: private.clone(), replaceable: false });
+        queue.push_back(QueuedPayload { payload: state.clone(), replaceable: true });
+
+        // the front is partially written and has to go out whole
+        assert_eq!(drop_replaceable(&mut queue, 3), (2, 2 * state.len()));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue[0].payload.as_ptr(), state.as_ptr());
+        assert_eq!(queue[1].payload.as_ptr(), private.as_ptr());
+
+        // a queue of private messages doesn't shrink, the client has to be disconnected instead
+        let mut queue: VecDeque<QueuedPayload> = (0..3).map(|_| QueuedPayload { payload: private.clone(), replaceable: false }).collect();
+        assert_eq!(drop_replaceable(&mut queue, 0), (0, 0));
+        assert_eq!(queue.len(), 3);
     }
 }
@@ -0,0 +1,201 @@
+use time::Duration;
+
+const WARN_AFTER_DROPPED: u32 = 20;
+const KICK_AFTER_DROPPED: u32 = 200;
+const FORGIVE_AFTER_SECONDS: i64 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict
+{
+    Accept,
+    Drop,
+    Warn,
+    Kick
+}
+
+pub struct TokenBucket
+{
+    rate: f64,
+    tokens: f64,
+    last_refill: Duration
+}
+
+/// Limits messages and bytes a single client may send, escalating from dropping to kicking.
+/// Times are the elapsed time of a `Clock`.
+pub struct RateLimiter
+{
+    messages: TokenBucket,
+    bytes: TokenBucket,
+    dropped: u32,
+    last_drop: Duration
+}
+
+impl TokenBucket
+{
+    /// The bucket holds one second worth of tokens, which is the allowed burst.
+    pub fn new(rate_per_second: u32, now: Duration) -> TokenBucket
+    {
+        TokenBucket
+        {
+            rate: rate_per_second as f64,
+            tokens: rate_per_second as f64,
+            last_refill: now
+        }
+    }
+
+    pub fn refill(&mut self, now: Duration)
+    {
+        let elapsed = (now - self.last_refill).num_microseconds().unwrap_or(0) as f64 / 1_000_000.0;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+    }
+
+    pub fn has(&self, amount: f64) -> bool
+    {
+        self.tokens >= amount
+    }
+
+    /// Never goes below empty, so a single burst can't lock a client out for longer than a refill.
+    pub fn take(&mut self, amount: f64)
+    {
+        self.tokens = (self.tokens - amount).max(0.0);
+    }
+}
+
+impl RateLimiter
+{
+    pub fn new(messages_per_second: u32, bytes_per_second: u32, now: Duration) -> RateLimiter
+    {
+        RateLimiter
+        {
+            messages: TokenBucket::new(messages_per_second, now),
+            bytes: TokenBucket::new(bytes_per_second, now),
+            dropped: 0,
+            last_drop: now
+        }
+    }
+
+    /// Charges bytes as they come off the socket, before they are split into messages, so a
+    /// client cannot get around the limit with partial or garbage frames. The bytes are read
+    /// already, so they empty the budget even when they exceed it.
+    pub fn charge_bytes(&mut self, bytes: usize, now: Duration) -> Verdict
+    {
+        self.bytes.refill(now);
+
+        let bytes = bytes as f64;
+        let within_budget = self.bytes.has(bytes);
+        self.bytes.take(bytes);
+
+        if within_budget
+        {
+            Verdict::Accept
+        }
+        else
+        {
+            self.escalate(now)
+        }
+    }
+
+    pub fn check_message(&mut self, now: Duration) -> Verdict
+    {
+        self.messages.refill(now);
+
+        if self.messages.has(1.0)
+        {
+            self.messages.take(1.0);
+            return Verdict::Accept;
+        }
+
+        self.escalate(now)
+    }
+
+    fn escalate(&mut self, now: Duration) -> Verdict
+    {
+        if now - self.last_drop > Duration::seconds(FORGIVE_AFTER_SECONDS)
+        {
+            self.dropped = 0;
+        }
+        self.last_drop = now;
+        self.dropped += 1;
+
+        match self.dropped
+        {
+            KICK_AFTER_DROPPED => Verdict::Kick,
+            WARN_AFTER_DROPPED => Verdict::Warn,
+            _ => Verdict::Drop
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use time::Duration;
+
+    use game_server::clock::{Clock, ManualClock};
+    use super::{RateLimiter, Verdict, WARN_AFTER_DROPPED, KICK_AFTER_DROPPED, FORGIVE_AFTER_SECONDS};
+
+    #[test]
+    fn bytes_within_the_budget_are_accepted()
+    {
+        let mut clock = ManualClock::new();
+        let mut limiter = RateLimiter::new(10, 1000, clock.elapsed());
+        assert_eq!(limiter.charge_bytes(600, clock.elapsed()), Verdict::Accept);
+        assert_eq!(limiter.charge_bytes(600, clock.elapsed()), Verdict::Drop);
+
+        clock.advance(Duration::milliseconds(700));
+        assert_eq!(limiter.charge_bytes(600, clock.elapsed()), Verdict::Accept);
+    }
+
+    #[test]
+    fn a_burst_does_not_lock_a_client_out()
+    {
+        let mut clock = ManualClock::new();
+        let mut limiter = RateLimiter::new(10, 1000, clock.elapsed());
+        assert_eq!(limiter.charge_bytes(100000, clock.elapsed()), Verdict::Drop);
+
+        clock.advance(Duration::seconds(1));
+        assert_eq!(limiter.charge_bytes(1000, clock.elapsed()), Verdict::Accept);
+    }
+
+    #[test]
+    fn flooding_escalates_from_dropping_to_a_warning_to_a_kick()
+    {
+        let clock = ManualClock::new();
+        let mut limiter = RateLimiter::new(1, 1000, clock.elapsed());
+        assert_eq!(limiter.check_message(clock.elapsed()), Verdict::Accept);
+
+        for dropped in 1..KICK_AFTER_DROPPED + 1
+        {
+            let expected = match dropped
+            {
+                KICK_AFTER_DROPPED => Verdict::Kick,
+                WARN_AFTER_DROPPED => Verdict::Warn,
+                _ => Verdict::Drop
+            };
+            assert_eq!(limiter.check_message(clock.elapsed()), expected);
+        }
+    }
+
+    #[test]
+    fn drops_are_forgiven_after_a_quiet_period()
+    {
+        let mut clock = ManualClock::new();
+        let mut limiter = RateLimiter::new(1, 1000, clock.elapsed());
+        assert_eq!(limiter.check_message(clock.elapsed()), Verdict::Accept);
+        for _ in 1..WARN_AFTER_DROPPED
+        {
+            assert_eq!(limiter.check_message(clock.elapsed()), Verdict::Drop);
+        }
+
+        clock.advance(Duration::seconds(FORGIVE_AFTER_SECONDS + 1));
+        assert_eq!(limiter.check_message(clock.elapsed()), Verdict::Accept);
+
+        // the count starts over, so the next drop is not the one that warns
+        for _ in 1..WARN_AFTER_DROPPED
+        {
+            assert_eq!(limiter.check_message(clock.elapsed()), Verdict::Drop);
+        }
+        assert_eq!(limiter.check_message(clock.elapsed()), Verdict::Warn);
+    }
+}
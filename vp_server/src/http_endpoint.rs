@@ -0,0 +1,194 @@
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::thread;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+use time;
+
+use metrics::SharedMetrics;
+use status::SharedStatus;
+
+/// Requests are served one at a time, so a request has to arrive in full within this time, however
+/// slowly its bytes trickle in. Anything slower is dropped instead of holding up the requests behind it.
+const REQUEST_TIMEOUT_MS: u64 = 5000;
+const MAX_REQUEST_LINE_BYTES: usize = 2048;
+const MAX_HEADER_BYTES: usize = 8 * 1024;
+
+enum Line
+{
+    Complete(String),
+    TooLong
+}
+
+pub fn spawn_http_endpoint(address: SocketAddr, metrics: SharedMetrics, status: SharedStatus)
+{
+    let listener = match TcpListener::bind(address)
+    {
+        Ok(listener) => listener,
+        Err(e) =>
+        {
+            error!("Failed to bind HTTP endpoint to {}, {}", address, e);
+            return;
+        }
+    };
+
+    info!("Serving metrics on http://{0}/metrics and status on http://{0}/status", address);
+
+    thread::spawn(move ||
+    {
+        for stream in listener.incoming()
+        {
+            match stream.and_then(|stream| handle_request(stream, &metrics, &status))
+            {
+                Ok(_) => {},
+                Err(e) => debug!("Failed to handle HTTP request, {}", e)
+            }
+        }
+    });
+}
+
+fn handle_request(stream: TcpStream, metrics: &SharedMetrics, status: &SharedStatus) -> io::Result<()>
+{
+    let deadline = time::precise_time_ns() + REQUEST_TIMEOUT_MS * 1000000;
+    try!(stream.set_write_timeout(Some(Duration::from_millis(REQUEST_TIMEOUT_MS))));
+
+    let mut reader = BufReader::new(try!(stream.try_clone()));
+    let request_line = match try!(read_line(&mut reader, deadline, MAX_REQUEST_LINE_BYTES))
+    {
+        Line::Complete(line) => line,
+        Line::TooLong => return respond(stream, "400 Bad Request", "text/plain", "Request line too long\n")
+    };
+
+    // headers are not needed, but have to be consumed before responding
+    let mut header_bytes = 0;
+    loop
+    {
+        match try!(read_line(&mut reader, deadline, MAX_HEADER_BYTES - header_bytes))
+        {
+            Line::Complete(ref header) if header.trim().len() == 0 => break,
+            Line::Complete(header) => header_bytes += header.len(),
+            Line::TooLong => return respond(stream, "431 Request Header Fields Too Large", "text/plain", "Request headers too large\n")
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+    match (method, path)
+    {
+        ("GET", "/metrics") =>
+        {
+            let body = metrics.lock().unwrap().render();
+            respond(stream, "200 OK", "text/plain; version=0.0.4", &body)
+        },
+        ("GET", "/status") =>
+        {
+            let body = status.lock().unwrap().render_json();
+            respond(stream, "200 OK", "application/json", &body)
+        },
+        ("GET", _) => respond(stream, "404 Not Found", "text/plain", "Not found\n"),
+        _ => respond(stream, "405 Method Not Allowed", "text/plain", "Method not allowed\n")
+    }
+}
+
+/// Reads up to and including the next newline, or what is left before the connection closes.
+fn read_line(reader: &mut BufReader<TcpStream>, deadline: u64, max_bytes: usize) -> io::Result<Line>
+{
+    let mut line = Vec::new();
+
+    loop
+    {
+        let now = time::precise_time_ns();
+        if now >= deadline
+        {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "request not received in time"));
+        }
+
+        // each read may only wait for what is left of the deadline, a zero timeout is not allowed
+        let remaining_ms = (deadline - now) / 1000000 + 1;
+        try!(reader.get_ref().set_read_timeout(Some(Duration::from_millis(remaining_ms))));
+
+        let (consumed, complete) =
+        {
+            let available = try!(reader.fill_buf());
+            if available.len() == 0
+            {
+                break;
+            }
+
+            let (consumed, complete) = match available.iter().position(|&byte| byte == b'\n')
+            {
+                Some(end) => (end + 1, true),
+                None => (available.len(), false)
+            };
+            line.extend(available[..consumed].iter().cloned());
+            (consumed, complete)
+        };
+        reader.consume(consumed);
+
+        if line.len() > max_bytes
+        {
+            return Ok(Line::TooLong);
+        }
+
+        if complete
+        {
+            break;
+        }
+    }
+
+    Ok(Line::Complete(String::from_utf8_lossy(&line).into_owned()))
+}
+
+fn respond(mut stream: TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()>
+{
+    try!(write!(stream, "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, content_type, body.len()));
+    try!(stream.write_all(body.as_bytes()));
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::io::{Read, Write};
+    use std::thread;
+    use std::net::{TcpListener, TcpStream};
+
+    use metrics;
+    use status;
+    use super::handle_request;
+
+    fn request(raw: String) -> String
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move ||
+        {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(raw.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        handle_request(stream, &metrics::shared(), &status::shared("Test")).unwrap();
+        client.join().unwrap()
+    }
+
+    #[test]
+    fn serves_status()
+    {
+        let response = request("GET /status HTTP/1.0\r\nHost: localhost\r\n\r\n".to_string());
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+    }
+
+    #[test]
+    fn rejects_oversized_request_lines()
+    {
+        let path: String = (0..3000).map(|_| 'a').collect();
+        let response = request(format!("GET /{} HTTP/1.0\r\n\r\n", path));
+        assert!(response.starts_with("HTTP/1.0 400 Bad Request\r\n"));
+    }
+}
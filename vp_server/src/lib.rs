@@ -0,0 +1,33 @@
+extern crate nalgebra as na;
+extern crate time;
+extern crate mio;
+#[macro_use]
+extern crate log;
+extern crate rustc_serialize;
+extern crate bincode;
+extern crate vp_shared;
+extern crate byteorder;
+extern crate libc;
+extern crate getopts;
+extern crate toml;
+extern crate nix;
+extern crate rand;
+
+pub mod game_server;
+pub mod vp_world;
+pub mod signals;
+pub mod config;
+pub mod rules_file;
+pub mod server_state;
+pub mod admin;
+pub mod metrics;
+pub mod http_endpoint;
+pub mod status;
+pub mod discovery;
+pub mod sessions;
+pub mod spectators;
+pub mod profiles;
+pub mod chat;
+pub mod stats_store;
+pub mod recording;
+pub mod playback;
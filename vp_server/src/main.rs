@@ -1,99 +1,471 @@
 #![feature(append)]
 
-extern crate nalgebra as na;
 extern crate time;
-extern crate mio;
 #[macro_use]
 extern crate log;
 extern crate env_logger;
-extern crate rustc_serialize;
 extern crate bincode;
 extern crate vp_shared;
-extern crate byteorder;
+extern crate vp_server;
 
-mod game_server;
-mod vp_world;
-
-use std::str::FromStr;
 use std::thread;
-use std::collections::HashSet;
+use std::process;
+use std::cmp;
+use std::net::SocketAddr;
+use std::collections::{HashSet, HashMap};
 
-use time::Duration;
 use bincode::SizeLimit;
-use bincode::rustc_serialize::{encode, decode};
+use bincode::rustc_serialize::{encode_into, decode};
+
+use vp_shared::{Event, PlayerId, PlayerCommand, PlayerProfile, ChatChannel, ChatMessage};
+use vp_server::game_server;
+use vp_server::game_server::{GameServerCommand, Frame};
+use vp_server::game_server::network_loop::{NetworkEvent, NetworkCommand, ClientId, Payload, frame_payload};
+use vp_server::vp_world::World;
+use vp_server::config::{ServerConfig, ConfigError};
+use vp_server::rules_file::RulesWatcher;
+use vp_server::server_state::ServerState;
+use vp_server::metrics::SharedMetrics;
+use vp_server::sessions::JoinOutcome;
+use vp_server::recording::Recorder;
+use vp_server::{signals, admin, metrics, http_endpoint, status, discovery, profiles, chat};
 
-use game_server::{GameServerCommand, Frame};
-use game_server::network_loop::{NetworkEvent, ClientId};
-use vp_shared::{Event, PlayerCommand};
-use vp_world::World;
+/// The smallest encoded command, a variant index without fields.
+const MIN_COMMAND_BYTES: usize = 4;
 
 fn main()
 {
     env_logger::init().ok().expect("Failed to init logger");
 
+    let config = match ServerConfig::load()
+    {
+        Ok(config) => config,
+        Err(ConfigError::HelpRequested(usage)) =>
+        {
+            println!("{}", usage);
+            return;
+        },
+        Err(e) =>
+        {
+            error!("{}", e);
+            process::exit(2);
+        }
+    };
+
     info!("Starting game server...");
 
-    let addr = FromStr::from_str("0.0.0.0:8000").ok().expect("Failed to parse host:port string");
+    signals::install_shutdown_handler();
 
-    let (mut game_loop, network_loop) = game_server::game_server(Duration::milliseconds(20), addr, 128);
+    let metrics = metrics::shared();
+    let status = status::shared(&config.server_name);
+    http_endpoint::spawn_http_endpoint(config.http_address, metrics.clone(), status.clone());
+    if config.discovery_enabled
+    {
+        let discovery_address = SocketAddr::new(config.bind_address.ip(), config.discovery_port);
+        discovery::spawn_discovery_responder(discovery_address, config.bind_address.port(), config.max_clients, status.clone());
+    }
 
-    thread::spawn(move ||
+    let admission = match config.admission_policy()
+    {
+        Ok(admission) => admission,
+        Err(e) =>
+        {
+            error!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    let stats = match config.stats_store()
+    {
+        Ok(stats) => stats,
+        Err(e) =>
+        {
+            error!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    let timing = config.loop_timing();
+    let (mut game_loop, network_loop) = game_server::game_server(timing, config.network_settings(), admission, metrics.clone());
+
+    let network_thread = thread::spawn(move ||
     {
         info!("Listening for incoming connections...");
         network_loop.run()
     });
 
-    info!("Running game world...");
-    let mut world = World::new();
+    info!("Running {} on {} at {} ticks per second...", config.game_mode, config.level, config.tick_rate);
+    let mut world = World::new(config.rules);
+    let mut state = ServerState::new(&config, stats);
+    let mut rules_watcher = config.rules_file.as_ref().map(|path| RulesWatcher::new(path, config.rule_overrides.clone()));
+    let scoreboard_interval_ticks = config.scoreboard_interval_seconds as u64 * config.tick_rate as u64;
+    let mut recorder = config.recording_settings().map(|settings| Recorder::start(settings, config.rules));
+    if let Some(ref recorder) = recorder
+    {
+        recorder.new_match(0, &state.level, &state.game_mode.to_string(), world.get_snapshot());
+    }
+
+    admin::spawn_stdin_console(game_loop.admin_channel());
+
     game_loop.run(|frame|
     {
-        let command_execution_events = get_command_execution_events(&world, &frame);
-        world.apply_events(&command_execution_events);
-        let update_events = world.update(frame.elapsed_seconds);
+        if signals::shutdown_requested()
+        {
+            info!("Shutdown requested, notifying clients...");
+            state.finish_match(&world);
+            let sends = get_broadcast_sends(&vec![Event::ServerShuttingDown], &frame.currently_connected_clients);
+            return GameServerCommand::Exit(vec![NetworkCommand::Send(sends)]);
+        }
+
+        state.track_connections(&frame);
+        metrics.lock().unwrap().record_frame(&frame);
+
+        let rules_events = get_rules_events(&world, rules_watcher.as_mut());
+        world.apply_events(&rules_events);
+        let (admin_events, mut network_commands) = admin::execute_commands(&mut world, &mut state, &frame);
+        world.apply_events(&admin_events);
+        let elapsed_seconds = if state.paused { 0.0 } else { frame.elapsed_seconds };
+        world.advance_time(elapsed_seconds);
+        let (command_execution_events, mut private_events, resync_clients, session_commands) = get_command_execution_events(&mut world, &mut state, &frame, config.max_commands_per_tick, &metrics);
+        if frame.tick % config.tick_rate as u64 == 0
+        {
+            private_events.extend(state.ping_events());
+        }
+        let update_events = if state.paused { vec![] } else { world.update(frame.elapsed_seconds) };
         world.apply_events(&update_events);
+        let scoreboard_events = get_scoreboard_events(&world, frame.tick, scoreboard_interval_ticks);
 
         let mut frame_events = Vec::new();
-        frame_events.extend(command_execution_events.iter());
-        frame_events.extend(update_events.iter());
-        let sends = get_sends(&frame_events, &world, &frame);
+        frame_events.extend(rules_events.iter().cloned());
+        frame_events.extend(admin_events.iter().cloned());
+        frame_events.extend(command_execution_events.iter().cloned());
+        frame_events.extend(update_events.iter().cloned());
+        frame_events.extend(scoreboard_events.into_iter());
+        if let Some(ref recorder) = recorder
+        {
+            recorder.record_tick(frame.tick, elapsed_seconds, &frame_events);
+            if frame_events.iter().any(|event| match *event { Event::MatchRestarted => true, _ => false })
+            {
+                recorder.new_match(frame.tick, &state.level, &state.game_mode.to_string(), world.get_snapshot());
+            }
+        }
+
+        let spectator_events = state.spectators.advance(frame.tick, elapsed_seconds, &frame_events);
+        let sends = get_sends(&frame_events, &spectator_events, &world, &state, &frame, &resync_clients);
+        let private_sends = get_private_sends(private_events);
+
+        status.lock().unwrap().update(&world, &state);
 
-        GameServerCommand::Continue(sends)
+        network_commands.insert(0, NetworkCommand::Send(sends));
+        network_commands.insert(1, NetworkCommand::SendPrivate(private_sends));
+        network_commands.extend(session_commands);
+        GameServerCommand::Continue(network_commands)
     });
+
+    network_thread.join().ok().expect("Network thread panicked");
+
+    if let Some(ref mut recorder) = recorder
+    {
+        recorder.stop();
+    }
+
+    let stats = game_loop.stats();
+    info!
+    (
+        "Game server stopped after {} ticks, {} overruns, frame time p50 {} us, p99 {} us",
+        stats.ticks,
+        stats.overruns,
+        stats.processing.p50.num_microseconds().unwrap(),
+        stats.processing.p99.num_microseconds().unwrap()
+    );
 }
 
-fn get_command_execution_events(world: &World, frame: &Frame) -> Vec<Event>
+fn get_rules_events(world: &World, rules_watcher: Option<&mut RulesWatcher>) -> Vec<Event>
 {
-    frame
-    .messages
-    .iter()
-    .flat_map(|message| match message
-    {
-        &NetworkEvent::ClientConnected(client_id) => world.create_player(client_id),
-        &NetworkEvent::ClientDisconnected(client_id) => world.remove_player(client_id),
-        &NetworkEvent::ClientDataReceived(client_id, ref data) =>
+    match rules_watcher.and_then(|watcher| watcher.poll(&world.rules()))
+    {
+        Some(Ok(rules)) =>
+        {
+            info!("Applying reloaded rules: {:?}", rules);
+            world.change_rules(rules)
+        },
+        Some(Err(e)) =>
         {
-            let commands = deserialize_commands(data);
-            commands
-                .into_iter()
-                .flat_map(|command| world.process_player_command(client_id, command))
-                .collect()
+            error!("Rejected rules reload, keeping current rules: {}", e);
+            vec![]
         },
+        None => vec![]
+    }
+}
+
+fn get_scoreboard_events(world: &World, tick: u64, interval_ticks: u64) -> Vec<Event>
+{
+    if interval_ticks != 0 && tick % interval_ticks == 0
+    {
+        vec![Event::ScoreboardUpdated(world.scoreboard())]
+    }
+    else
+    {
+        vec![]
+    }
+}
+
+fn get_command_execution_events(world: &mut World, state: &mut ServerState, frame: &Frame, max_commands_per_tick: usize, metrics: &SharedMetrics)
+    -> (Vec<Event>, Vec<(ClientId, Event)>, Vec<ClientId>, Vec<NetworkCommand>)
+{
+    let mut events = Vec::new();
+    let mut private_events = Vec::new();
+    let mut resync_clients = Vec::new();
+    let mut network_commands = Vec::new();
+    let mut applied_commands = HashMap::new();
+
+    for player_id in state.sessions.expire(frame.tick)
+    {
+        info!("Session of player {} expired", player_id);
+        state.forget_player(world, player_id);
+        events.push(chat::system_message(format!("{} left the game", world.player_name(player_id).unwrap_or("?"))));
+        events.extend(world.remove_player(player_id));
+    }
+    world.apply_events(&events);
+
+    for message in frame.messages.iter()
+    {
+        match message
+        {
+            &NetworkEvent::ClientConnected(_, _) => {},
+            &NetworkEvent::ClientLagged(_) => {},
+            &NetworkEvent::ClientDisconnected(client_id) =>
+            {
+                state.spectators.forget(client_id);
+                if let Some(player_id) = state.sessions.detach(client_id, frame.tick)
+                {
+                    info!("Player {} lost its connection, keeping it for a reconnect", player_id);
+                    // nobody controls the player until it is resumed
+                    let stop_events = world.process_player_command(player_id, PlayerCommand::ChangeMovementDirection(None));
+                    world.apply_events(&stop_events);
+                    events.extend(stop_events);
+                }
+            },
+            &NetworkEvent::ClientDataReceived(client_id, ref data) =>
+            {
+                let commands = deserialize_commands(data, metrics);
+
+                let applied = applied_commands.entry(client_id).or_insert(0);
+                let allowed = cmp::min(commands.len(), max_commands_per_tick - *applied);
+                if allowed < commands.len()
+                {
+                    debug!("Dropping {} commands from {} over the per tick cap", commands.len() - allowed, client_id);
+                    metrics.lock().unwrap().record_dropped_commands(commands.len() - allowed);
+                }
+                *applied += allowed;
+
+                for command in commands.into_iter().take(allowed)
+                {
+                    let first_new_event = events.len();
+                    match command
+                    {
+                        PlayerCommand::Join(token, name) => match state.sessions.join(client_id, token, || world.allocate_player_id())
+                        {
+                            JoinOutcome::Created(player_id, token) => match profiles::validate_name(&name)
+                            {
+                                Ok(name) =>
+                                {
+                                    let name = profiles::deduplicate_name(&name, &world.player_names());
+                                    info!("Client {} joined as player {}, {}", client_id, player_id, name);
+                                    state.spectators.forget(client_id);
+                                    let team = state.team_for_new_player(world);
+                                    events.push(chat::system_message(format!("{} joined the game", name)));
+                                    events.extend(world.create_player(player_id, PlayerProfile { name: name, team: team }));
+                                    events.push(Event::PlayerJoined(player_id));
+                                    private_events.push((client_id, Event::SessionStarted(player_id, token)));
+                                    resync_clients.push(client_id);
+                                },
+                                Err(e) =>
+                                {
+                                    // the client stays a spectator and can join again with a valid name
+                                    state.sessions.end(player_id);
+                                    private_events.push((client_id, Event::CommandRejected(e)));
+                                }
+                            },
+                            JoinOutcome::Resumed(player_id, token, previous_client_id) =>
+                            {
+                                info!("Client {} resumed player {}", client_id, player_id);
+                                if let Some(previous_client_id) = previous_client_id
+                                {
+                                    info!("Disconnecting client {}, its player was resumed elsewhere", previous_client_id);
+                                    network_commands.push(NetworkCommand::Disconnect(previous_client_id));
+                                }
+                                state.spectators.forget(client_id);
+                                private_events.push((client_id, Event::SessionStarted(player_id, token)));
+                                resync_clients.push(client_id);
+                            },
+                            JoinOutcome::AlreadyJoined => debug!("Ignoring repeated join from {}", client_id)
+                        },
+                        PlayerCommand::Rename(name) => match state.sessions.player_of(client_id)
+                        {
+                            Some(player_id) => match profiles::validate_name(&name)
+                            {
+                                Ok(name) =>
+                                {
+                                    if state.renames.try_rename(player_id, frame.tick)
+                                    {
+                                        let name = profiles::deduplicate_name(&name, &world.other_player_names(player_id));
+                                        info!("Player {} renamed to {}", player_id, name);
+                                        state.chat.renamed(world.player_name(player_id).unwrap_or(""), &name);
+                                        events.extend(world.rename_player(player_id, name));
+                                    }
+                                    else
+                                    {
+                                        private_events.push((client_id, Event::CommandRejected("Renaming too often, try again later".to_string())));
+                                    }
+                                },
+                                Err(e) => private_events.push((client_id, Event::CommandRejected(e)))
+                            },
+                            None => debug!("Ignoring rename from {} before it joined", client_id)
+                        },
+                        PlayerCommand::Chat(channel, text) => match state.sessions.player_of(client_id)
+                        {
+                            Some(player_id) => match state.chat.check(player_id, world.player_name(player_id).unwrap_or(""), &text, frame.tick)
+                            {
+                                Ok(text) =>
+                                {
+                                    let message = Event::Chat(ChatMessage { channel: channel, sender: Some(player_id), text: text });
+                                    match get_chat_recipients(world, state, player_id, channel)
+                                    {
+                                        // everyone sees all chat, spectators with the broadcast delay
+                                        None => events.push(message),
+                                        Some(Ok(recipients)) => private_events.extend(recipients.into_iter().map(|recipient| (recipient, message.clone()))),
+                                        Some(Err(e)) => private_events.push((client_id, Event::CommandRejected(e)))
+                                    }
+                                },
+                                Err(e) => private_events.push((client_id, Event::CommandRejected(e)))
+                            },
+                            None => debug!("Ignoring chat from {} before it joined", client_id)
+                        },
+                        PlayerCommand::Spectate => match state.sessions.player_of(client_id)
+                        {
+                            Some(player_id) =>
+                            {
+                                info!("Player {} switched to spectating", player_id);
+                                state.sessions.end(player_id);
+                                state.forget_player(world, player_id);
+                                events.extend(world.remove_player(player_id));
+                                private_events.push((client_id, Event::Spectating(None)));
+                                resync_clients.push(client_id);
+                            },
+                            None => debug!("Ignoring spectate from {}, it is already spectating", client_id)
+                        },
+                        PlayerCommand::RequestScoreboard =>
+                        {
+                            // spectators get the scoreboard of the delayed world they are watching
+                            let scoreboard = match state.sessions.player_of(client_id)
+                            {
+                                Some(_) => world.scoreboard(),
+                                None => state.spectators.scoreboard()
+                            };
+                            private_events.push((client_id, Event::ScoreboardUpdated(scoreboard)));
+                        },
+                        PlayerCommand::Pong(pinged_at) => state.record_pong(client_id, pinged_at),
+                        PlayerCommand::Follow(player_id) => match state.sessions.player_of(client_id)
+                        {
+                            Some(_) => debug!("Ignoring follow from {}, it is playing", client_id),
+                            None =>
+                            {
+                                let following = state.spectators.follow(client_id, player_id);
+                                private_events.push((client_id, Event::Spectating(following)));
+                            }
+                        },
+                        command => match state.sessions.player_of(client_id)
+                        {
+                            // moving and firing would change the world while its clock stands still
+                            Some(_) if state.paused => private_events.push((client_id, Event::CommandRejected("The game is paused".to_string()))),
+                            Some(player_id) => events.extend(world.process_player_command(player_id, command)),
+                            None => debug!("Ignoring command from {} before it joined", client_id)
+                        }
+                    }
+
+                    let kill_messages = get_kill_messages(world, &events[first_new_event..]);
+                    events.extend(kill_messages);
+
+                    // later commands this tick must see earlier ones, a second shot has to hit the fire cooldown
+                    world.apply_events(&events[first_new_event..]);
+                }
+            },
+        }
+    }
+
+    (events, private_events, resync_clients, network_commands)
+}
+
+fn get_kill_messages(world: &World, events: &[Event]) -> Vec<Event>
+{
+    events.iter().filter_map(|event| match *event
+    {
+        Event::PlayerKilled(ref kill) =>
+        {
+            let name = |player_id| world.player_name(player_id).unwrap_or("?").to_string();
+            Some(chat::system_message(format!("{} killed {}", name(kill.killer), name(kill.victim))))
+        },
+        _ => None
     })
     .collect()
 }
 
-fn get_sends(frame_events: &Vec<Event>, world: &World, frame: &Frame) -> Vec<(ClientId, Vec<u8>)>
+/// Clients a team or whisper message goes to, the sender included. All chat returns `None`.
+fn get_chat_recipients(world: &World, state: &ServerState, sender: PlayerId, channel: ChatChannel) -> Option<Result<Vec<ClientId>, String>>
+{
+    let player_ids = match channel
+    {
+        ChatChannel::All => return None,
+        ChatChannel::Team => match world.player_team(sender)
+        {
+            Some(team) => world.team_members(team),
+            None => return Some(Err("There are no teams in this game mode".to_string()))
+        },
+        ChatChannel::Whisper(recipient) =>
+        {
+            if state.sessions.client_of(recipient).is_none()
+            {
+                return Some(Err(format!("Player {} is not connected", recipient)));
+            }
+            if recipient == sender { vec![sender] } else { vec![sender, recipient] }
+        }
+    };
+
+    Some(Ok(player_ids.into_iter().filter_map(|player_id| state.sessions.client_of(player_id)).collect()))
+}
+
+fn get_sends(frame_events: &Vec<Event>, spectator_events: &Vec<Event>, world: &World, state: &ServerState, frame: &Frame, resync_clients: &[ClientId])
+    -> Vec<(ClientId, Payload)>
 {
-    let just_connected_clients = frame.get_just_connected_clients::<HashSet<ClientId>>();
+    let mut snapshot_clients = frame.get_clients_needing_snapshot::<HashSet<ClientId>>();
+    snapshot_clients.extend(resync_clients.iter().cloned());
 
-    let mut snapshots = if just_connected_clients.len() != 0
+    let (players, spectators): (Vec<ClientId>, Vec<ClientId>) = frame
+        .currently_connected_clients
+        .iter()
+        .cloned()
+        .partition(|client_id| state.sessions.player_of(*client_id).is_some());
+
+    let mut sends = get_audience_sends(frame_events, || world.get_snapshot(), &players, &snapshot_clients);
+    sends.extend(get_audience_sends(spectator_events, || state.spectators.snapshot(), &spectators, &snapshot_clients));
+    sends
+}
+
+fn get_audience_sends<F>(events: &Vec<Event>, get_snapshot: F, client_ids: &[ClientId], snapshot_clients: &HashSet<ClientId>) -> Vec<(ClientId, Payload)>
+    where F: Fn() -> Vec<Event>
+{
+    let (snapshot_ids, update_ids): (Vec<ClientId>, Vec<ClientId>) = client_ids
+        .iter()
+        .cloned()
+        .partition(|client_id| snapshot_clients.contains(client_id));
+
+    let mut snapshots = if snapshot_ids.len() != 0
     {
-        let snapshot = world.get_snapshot();
-        let serialized_snapshot = serialize_events(&snapshot);
+        let serialized_snapshot = serialize_events(&get_snapshot());
 
-        just_connected_clients
-            .iter()
-            .cloned()
+        snapshot_ids
+            .into_iter()
             .map(|client_id| (client_id, serialized_snapshot.clone()))
             .collect()
     }
@@ -102,45 +474,100 @@ fn get_sends(frame_events: &Vec<Event>, world: &World, frame: &Frame) -> Vec<(Cl
         vec![]
     };
 
-    let mut updates = if frame_events.len() != 0
-                      && just_connected_clients.len() != frame.currently_connected_clients.len()
+    let mut updates = if events.len() != 0 && update_ids.len() != 0
     {
-        let broadcast = serialize_events(frame_events);
-        frame
-            .currently_connected_clients
-            .iter()
-            .cloned()
-            .filter(|client_id| !just_connected_clients.contains(client_id))
-            .map(|client_id| (client_id, broadcast.clone()))
-            .collect()
+        get_broadcast_sends(events, &update_ids)
     }
     else
     {
         vec![]
     };
 
-    let mut events = Vec::new();
-    events.append(&mut snapshots);
-    events.append(&mut updates);
+    let mut sends = Vec::new();
+    sends.append(&mut snapshots);
+    sends.append(&mut updates);
 
-    events
+    sends
 }
 
-fn serialize_events(events: &Vec<Event>) -> Vec<u8>
+fn get_broadcast_sends(events: &Vec<Event>, client_ids: &[ClientId]) -> Vec<(ClientId, Payload)>
+{
+    let broadcast = serialize_events(events);
+    client_ids
+        .iter()
+        .cloned()
+        .map(|client_id| (client_id, broadcast.clone()))
+        .collect()
+}
+
+fn get_private_sends(private_events: Vec<(ClientId, Event)>) -> Vec<(ClientId, Payload)>
+{
+    private_events
+        .into_iter()
+        .map(|(client_id, event)| (client_id, serialize_events(&vec![event])))
+        .collect()
+}
+
+fn serialize_events(events: &Vec<Event>) -> Payload
 {
     //debug!("Sending: {:?}", events);
-    encode(events, SizeLimit::Infinite).unwrap()
+    frame_payload(|buffer| encode_into(events, buffer, SizeLimit::Infinite).unwrap())
 }
 
-fn deserialize_commands(data: &[u8]) -> Vec<PlayerCommand>
+fn deserialize_commands(data: &[u8], metrics: &SharedMetrics) -> Vec<PlayerCommand>
 {
-    match decode(data)
+    match decode_commands(data)
     {
         Ok(commands) => commands,
         Err(e) =>
         {
             error!("Error decoding commands, error: {}", e);
+            metrics.lock().unwrap().record_decode_error();
             vec![]
         }
     }
 }
+
+fn decode_commands(data: &[u8]) -> Result<Vec<PlayerCommand>, String>
+{
+    // the decoder reserves room for the declared count up front, a few bytes must not claim millions of commands
+    let count = try!(decode::<u64>(data).map_err(|e| e.to_string()));
+    let max_count = (data.len().saturating_sub(8) / MIN_COMMAND_BYTES) as u64;
+    if count > max_count
+    {
+        return Err(format!("{} commands declared in a message of {} bytes", count, data.len()));
+    }
+
+    decode(data).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use bincode::SizeLimit;
+    use bincode::rustc_serialize::encode;
+
+    use vp_shared::{PlayerCommand, Direction};
+    use super::decode_commands;
+
+    #[test]
+    fn commands_are_decoded()
+    {
+        let commands = vec![PlayerCommand::Spectate, PlayerCommand::Fire(Direction::Up), PlayerCommand::Rename("Someone".to_string())];
+        let data = encode(&commands, SizeLimit::Infinite).unwrap();
+        assert_eq!(decode_commands(&data).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn command_counts_beyond_the_message_size_are_rejected()
+    {
+        let mut data = encode(&(1u64 << 40), SizeLimit::Infinite).unwrap();
+        data.extend([0u8; 4].iter().cloned());
+        assert!(decode_commands(&data).is_err());
+
+        let mut data = encode(&vec![PlayerCommand::Spectate; 2], SizeLimit::Infinite).unwrap();
+        data[7] = 3;
+        assert!(decode_commands(&data).is_err());
+        assert!(decode_commands(&[0u8; 4]).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::collections::BTreeMap;
+
+use time::Duration;
+
+use vp_shared::RejectReason;
+
+use game_server::Frame;
+use game_server::network_loop::ClientId;
+
+const FRAME_TIME_BUCKETS: &'static [f64] = &[0.0005, 0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1];
+
+pub type SharedMetrics = Arc<Mutex<Metrics>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DisconnectReason
+{
+    Hangup,
+    SocketError,
+    ReadFailed,
+    WriteFailed,
+    Kicked,
+    Banned,
+    SlowConsumer,
+    Flooding,
+    OversizedMessage
+}
+
+pub struct Metrics
+{
+    connected_clients: usize,
+    ticks: u64,
+    frame_time: Histogram,
+    sleep_time: Histogram,
+    bytes_in: u64,
+    bytes_out: u64,
+    messages_in: u64,
+    messages_out: u64,
+    decode_errors: u64,
+    dropped_messages: u64,
+    rate_limited_messages: u64,
+    dropped_commands: u64,
+    disconnects: BTreeMap<DisconnectReason, u64>,
+    rejections: BTreeMap<RejectReason, u64>,
+    send_queue_depths: Vec<(ClientId, usize, usize)>
+}
+
+struct Histogram
+{
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64
+}
+
+pub fn shared() -> SharedMetrics
+{
+    Arc::new(Mutex::new(Metrics::new()))
+}
+
+impl Metrics
+{
+    fn new() -> Metrics
+    {
+        Metrics
+        {
+            connected_clients: 0,
+            ticks: 0,
+            frame_time: Histogram::new(),
+            sleep_time: Histogram::new(),
+            bytes_in: 0,
+            bytes_out: 0,
+            messages_in: 0,
+            messages_out: 0,
+            decode_errors: 0,
+            dropped_messages: 0,
+            rate_limited_messages: 0,
+            dropped_commands: 0,
+            disconnects: BTreeMap::new(),
+            rejections: BTreeMap::new(),
+            send_queue_depths: Vec::new()
+        }
+    }
+
+    pub fn record_frame(&mut self, frame: &Frame)
+    {
+        self.connected_clients = frame.currently_connected_clients.len();
+
+        // frame stats describe the previous tick, which is only recorded once
+        if frame.stats.ticks > self.ticks
+        {
+            self.ticks = frame.stats.ticks;
+            self.frame_time.observe(seconds(frame.stats.last.processing));
+            self.sleep_time.observe(seconds(frame.stats.last.sleep));
+        }
+    }
+
+    pub fn record_received(&mut self, bytes: usize, messages: usize)
+    {
+        self.bytes_in += bytes as u64;
+        self.messages_in += messages as u64;
+    }
+
+    pub fn record_enqueued(&mut self, messages: usize)
+    {
+        self.messages_out += messages as u64;
+    }
+
+    pub fn record_written(&mut self, bytes: usize)
+    {
+        self.bytes_out += bytes as u64;
+    }
+
+    pub fn record_decode_error(&mut self)
+    {
+        self.decode_errors += 1;
+    }
+
+    pub fn record_dropped(&mut self, messages: usize)
+    {
+        self.dropped_messages += messages as u64;
+    }
+
+    pub fn record_rate_limited(&mut self, messages: usize)
+    {
+        self.rate_limited_messages += messages as u64;
+    }
+
+    pub fn record_dropped_commands(&mut self, commands: usize)
+    {
+        self.dropped_commands += commands as u64;
+    }
+
+    pub fn record_disconnect(&mut self, reason: DisconnectReason)
+    {
+        *self.disconnects.entry(reason).or_insert(0) += 1;
+    }
+
+    pub fn record_rejected(&mut self, reason: RejectReason)
+    {
+        *self.rejections.entry(reason).or_insert(0) += 1;
+    }
+
+    pub fn set_send_queue_depths(&mut self, send_queue_depths: Vec<(ClientId, usize, usize)>)
+    {
+        self.send_queue_depths = send_queue_depths;
+    }
+
+    pub fn render(&self) -> String
+    {
+        let mut out = String::new();
+
+        gauge(&mut out, "vp_connected_clients", "Number of connected clients.", self.connected_clients as u64);
+        counter(&mut out, "vp_ticks_total", "Number of simulation ticks run.", self.ticks);
+        self.frame_time.render(&mut out, "vp_frame_processing_seconds", "Time spent processing a tick.");
+        self.sleep_time.render(&mut out, "vp_frame_sleep_seconds", "Time spent waiting for the next tick.");
+        counter(&mut out, "vp_received_bytes_total", "Bytes received from clients.", self.bytes_in);
+        counter(&mut out, "vp_sent_bytes_total", "Bytes written to clients.", self.bytes_out);
+        counter(&mut out, "vp_received_messages_total", "Messages received from clients.", self.messages_in);
+        counter(&mut out, "vp_sent_messages_total", "Messages queued for clients.", self.messages_out);
+        counter(&mut out, "vp_decode_errors_total", "Client messages that failed to decode.", self.decode_errors);
+        counter(&mut out, "vp_dropped_messages_total", "Queued messages dropped for slow consumers.", self.dropped_messages);
+        counter(&mut out, "vp_rate_limited_messages_total", "Client messages dropped by rate limiting.", self.rate_limited_messages);
+        counter(&mut out, "vp_dropped_commands_total", "Client commands over the per tick cap.", self.dropped_commands);
+
+        header(&mut out, "vp_disconnects_total", "Client disconnects by reason.", "counter");
+        for (reason, count) in self.disconnects.iter()
+        {
+            writeln!(out, "vp_disconnects_total{{reason=\"{}\"}} {}", reason.label(), count).unwrap();
+        }
+
+        header(&mut out, "vp_rejected_connections_total", "Connections refused at admission by reason.", "counter");
+        for (reason, count) in self.rejections.iter()
+        {
+            writeln!(out, "vp_rejected_connections_total{{reason=\"{}\"}} {}", reject_label(reason), count).unwrap();
+        }
+
+        header(&mut out, "vp_send_queue_depth", "Messages waiting in a client's send queue.", "gauge");
+        for &(client_id, messages, _) in self.send_queue_depths.iter()
+        {
+            writeln!(out, "vp_send_queue_depth{{client=\"{}\"}} {}", client_id, messages).unwrap();
+        }
+
+        header(&mut out, "vp_send_queue_bytes", "Bytes waiting in a client's send queue.", "gauge");
+        for &(client_id, _, bytes) in self.send_queue_depths.iter()
+        {
+            writeln!(out, "vp_send_queue_bytes{{client=\"{}\"}} {}", client_id, bytes).unwrap();
+        }
+
+        out
+    }
+}
+
+impl DisconnectReason
+{
+    fn label(&self) -> &'static str
+    {
+        match *self
+        {
+            DisconnectReason::Hangup       => "hangup",
+            DisconnectReason::SocketError  => "socket_error",
+            DisconnectReason::ReadFailed   => "read_failed",
+            DisconnectReason::WriteFailed  => "write_failed",
+            DisconnectReason::Kicked       => "kicked",
+            DisconnectReason::Banned       => "banned",
+            DisconnectReason::SlowConsumer => "slow_consumer",
+            DisconnectReason::Flooding     => "flooding",
+            DisconnectReason::OversizedMessage => "oversized_message",
+        }
+    }
+}
+
+fn reject_label(reason: &RejectReason) -> &'static str
+{
+    match *reason
+    {
+        RejectReason::ServerFull         => "server_full",
+        RejectReason::Banned             => "banned",
+        RejectReason::TooManyConnections => "too_many_connections",
+    }
+}
+
+impl Histogram
+{
+    fn new() -> Histogram
+    {
+        Histogram { buckets: vec![0; FRAME_TIME_BUCKETS.len()], count: 0, sum: 0.0 }
+    }
+
+    fn observe(&mut self, value: f64)
+    {
+        for (bucket, &upper_bound) in self.buckets.iter_mut().zip(FRAME_TIME_BUCKETS.iter())
+        {
+            if value <= upper_bound
+            {
+                *bucket += 1;
+            }
+        }
+
+        self.count += 1;
+        self.sum += value;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str)
+    {
+        header(out, name, help, "histogram");
+        for (count, upper_bound) in self.buckets.iter().zip(FRAME_TIME_BUCKETS.iter())
+        {
+            writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, upper_bound, count).unwrap();
+        }
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count).unwrap();
+        writeln!(out, "{}_sum {}", name, self.sum).unwrap();
+        writeln!(out, "{}_count {}", name, self.count).unwrap();
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str)
+{
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64)
+{
+    header(out, name, help, "counter");
+    writeln!(out, "{} {}", name, value).unwrap();
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64)
+{
+    header(out, name, help, "gauge");
+    writeln!(out, "{} {}", name, value).unwrap();
+}
+
+fn seconds(duration: Duration) -> f64
+{
+    duration.num_microseconds().unwrap() as f64 / 1_000_000.0
+}
@@ -0,0 +1,409 @@
+use std::io;
+use std::cmp;
+use std::io::{Read, BufReader};
+use std::fs::File;
+
+use bincode::rustc_serialize::decode;
+use byteorder;
+use byteorder::{ReadBytesExt, BigEndian};
+
+use vp_shared::{Event, PROTOCOL_VERSION};
+use vp_shared::replay::{ReplayHeader, ReplayRecord, REPLAY_FORMAT_VERSION};
+use vp_shared::simulation::Rules;
+
+use vp_world::World;
+
+/// A replay file read into memory.
+pub struct Replay
+{
+    pub header: ReplayHeader,
+    records: Vec<ReplayRecord>,
+    /// Tick and record index of every keyframe, in order.
+    keyframes: Vec<(u64, usize)>
+}
+
+impl Replay
+{
+    pub fn load(path: &str) -> Result<Replay, String>
+    {
+        let file = try!(File::open(path).map_err(|e| format!("Failed to open replay {}: {}", path, e)));
+        let mut remaining = try!(file.metadata().map_err(|e| format!("Failed to read replay {}: {}", path, e))).len();
+        let mut reader = BufReader::new(file);
+
+        let header = match try!(read_record(&mut reader, &mut remaining).map_err(|e| format!("Failed to read replay {}: {}", path, e)))
+        {
+            Some(ReplayRecord::Header(header)) => header,
+            _ => return Err(format!("{} is not a replay, it does not start with a header", path))
+        };
+
+        if header.format_version != REPLAY_FORMAT_VERSION
+        {
+            return Err(format!("{} has replay format {}, expected {}", path, header.format_version, REPLAY_FORMAT_VERSION));
+        }
+
+        // the events are sent to clients as they are, older clients could not decode them
+        if header.protocol_version != PROTOCOL_VERSION
+        {
+            return Err(format!("{} was recorded with protocol version {}, expected {}", path, header.protocol_version, PROTOCOL_VERSION));
+        }
+
+        if header.tick_rate == 0 || header.tick_rate > 1000
+        {
+            return Err(format!("{} has tick rate {}, expected between 1 and 1000", path, header.tick_rate));
+        }
+
+        let mut records = Vec::new();
+        let mut keyframes = Vec::new();
+        loop
+        {
+            match read_record(&mut reader, &mut remaining)
+            {
+                Ok(Some(record)) =>
+                {
+                    if let ReplayRecord::Keyframe(tick, _) = record
+                    {
+                        keyframes.push((tick, records.len()));
+                    }
+                    records.push(record);
+                },
+                Ok(None) => break,
+                // a server that crashed leaves a partial last record behind
+                Err(e) =>
+                {
+                    warn!("Replay {} ends with an unreadable record, playing up to it: {}", path, e);
+                    break;
+                }
+            }
+        }
+
+        if keyframes.is_empty()
+        {
+            return Err(format!("{} has no keyframe to start playing from", path));
+        }
+
+        Ok(Replay { header: header, records: records, keyframes: keyframes })
+    }
+
+    pub fn first_tick(&self) -> u64
+    {
+        self.keyframes[0].0
+    }
+
+    pub fn last_tick(&self) -> u64
+    {
+        self.records.iter().rev().map(record_tick).next().unwrap_or(0)
+    }
+
+    pub fn keyframe_count(&self) -> usize
+    {
+        self.keyframes.len()
+    }
+}
+
+/// Plays a replay back into a world, one tick at a time or by seeking.
+pub struct Playback
+{
+    replay: Replay,
+    world: World,
+    /// Index of the next record to play.
+    next_record: usize,
+    /// Fractional tick the playback is at, so slow speeds still make progress.
+    tick: f64,
+    pub speed: f64,
+    pub paused: bool
+}
+
+impl Playback
+{
+    pub fn new(replay: Replay) -> Playback
+    {
+        let first_tick = replay.first_tick();
+        let mut playback = Playback
+        {
+            replay: replay,
+            world: World::new(Rules::default()),
+            next_record: 0,
+            tick: 0.0,
+            speed: 1.0,
+            paused: false
+        };
+        playback.seek(first_tick);
+        playback
+    }
+
+    pub fn replay(&self) -> &Replay
+    {
+        &self.replay
+    }
+
+    pub fn tick(&self) -> u64
+    {
+        self.tick as u64
+    }
+
+    pub fn finished(&self) -> bool
+    {
+        self.next_record >= self.replay.records.len()
+    }
+
+    pub fn snapshot(&self) -> Vec<Event>
+    {
+        self.world.get_snapshot()
+    }
+
+    /// Jumps to a tick by loading the keyframe before it and playing the ticks in between.
+    pub fn seek(&mut self, tick: u64)
+    {
+        let tick = cmp::max(tick, self.replay.first_tick());
+        let &(keyframe_tick, keyframe_index) = self.replay.keyframes
+            .iter()
+            .take_while(|&&(keyframe_tick, _)| keyframe_tick <= tick)
+            .last()
+            .unwrap_or(&self.replay.keyframes[0]);
+
+        if let ReplayRecord::Keyframe(_, ref snapshot) = self.replay.records[keyframe_index]
+        {
+            self.world.apply_events(snapshot);
+        }
+
+        self.next_record = keyframe_index + 1;
+        self.tick = keyframe_tick as f64;
+        self.play_until(tick);
+        self.tick = tick as f64;
+    }
+
+    /// Moves playback on by one server frame and returns the events of the ticks passed.
+    pub fn advance(&mut self) -> Vec<Event>
+    {
+        if self.paused
+        {
+            return vec![];
+        }
+
+        self.tick += self.speed;
+        let tick = self.tick as u64;
+        self.play_until(tick)
+    }
+
+    fn play_until(&mut self, tick: u64) -> Vec<Event>
+    {
+        let mut events = Vec::new();
+        while self.next_record < self.replay.records.len() && record_tick(&self.replay.records[self.next_record]) <= tick
+        {
+            // keyframes repeat what the ticks before them did
+            if let ReplayRecord::Tick(_, elapsed_seconds, ref tick_events) = self.replay.records[self.next_record]
+            {
+                self.world.advance_time(elapsed_seconds);
+                self.world.apply_events(tick_events);
+                events.extend(tick_events.iter().cloned());
+            }
+
+            self.next_record += 1;
+        }
+
+        events
+    }
+}
+
+fn record_tick(record: &ReplayRecord) -> u64
+{
+    match *record
+    {
+        ReplayRecord::Header(_) => 0,
+        ReplayRecord::Keyframe(tick, _) => tick,
+        ReplayRecord::Tick(tick, _, _) => tick
+    }
+}
+
+/// Reads the next length prefixed record, `None` at the end of the file. `remaining` is the number
+/// of bytes left in the file, a length beyond it is corrupt and not worth allocating for.
+fn read_record<R: Read>(reader: &mut R, remaining: &mut u64) -> io::Result<Option<ReplayRecord>>
+{
+    let length = match reader.read_u32::<BigEndian>()
+    {
+        Ok(length) => length as u64,
+        Err(byteorder::Error::UnexpectedEOF) => return Ok(None),
+        Err(byteorder::Error::Io(e)) => return Err(e)
+    };
+
+    *remaining = remaining.saturating_sub(4);
+    if length > *remaining
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("record of {} bytes with {} bytes left", length, *remaining)));
+    }
+    *remaining -= length;
+
+    let mut buffer = vec![0; length as usize];
+    try!(reader.read_exact(&mut buffer));
+    decode(&buffer).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+
+    use time;
+    use bincode::SizeLimit;
+    use bincode::rustc_serialize::encode;
+    use byteorder::{WriteBytesExt, BigEndian};
+
+    use vp_shared::{Event, PlayerCommand, PlayerProfile, Direction, PROTOCOL_VERSION};
+    use vp_shared::replay::{ReplayHeader, ReplayRecord, REPLAY_FORMAT_VERSION};
+    use vp_shared::simulation::Rules;
+
+    use recording::{Recorder, RecordingSettings, replay_files};
+    use vp_world::World;
+    use super::{Replay, Playback};
+
+    const TICKS: u64 = 40;
+
+    fn temp_directory(name: &str) -> String
+    {
+        let directory = env::temp_dir().join(format!("vp_{}_{}", name, time::precise_time_ns()));
+        directory.to_str().unwrap().to_string()
+    }
+
+    fn paused(tick: u64) -> bool
+    {
+        tick >= 12 && tick <= 18
+    }
+
+    /// Position of the only player and the match time a snapshot describes.
+    fn describe(snapshot: &[Event]) -> (f32, f32)
+    {
+        let mut x = None;
+        let mut match_time = None;
+        for event in snapshot
+        {
+            match *event
+            {
+                Event::PlayerCreated(_, _, player_state) => x = Some(player_state.position.x),
+                Event::ScoreboardUpdated(ref scoreboard) => match_time = Some(scoreboard.match_time),
+                _ => {}
+            }
+        }
+        (x.unwrap(), match_time.unwrap())
+    }
+
+    /// Plays a match with a pause in it, records it and returns the replay and what the live world
+    /// looked like after every tick.
+    fn record_match(directory: &str) -> (Replay, Vec<(f32, f32)>)
+    {
+        let settings = RecordingSettings { directory: directory.to_string(), tick_rate: 50, keyframe_ticks: 10, max_file_bytes: 1 << 30, keep_files: 10 };
+        let mut recorder = Recorder::start(settings, Rules::default());
+        let mut world = World::new(Rules::default());
+        recorder.new_match(0, "arena", "deathmatch", world.get_snapshot());
+
+        let player_id = world.allocate_player_id();
+        let mut live = vec![(0.0, 0.0)];
+        for tick in 1..TICKS + 1
+        {
+            let elapsed_seconds = if paused(tick) { 0.0 } else { 0.02 };
+            world.advance_time(elapsed_seconds);
+
+            let mut events = Vec::new();
+            if tick == 1
+            {
+                events = world.create_player(player_id, PlayerProfile { name: "A".to_string(), team: None });
+                world.apply_events(&events);
+                let command_events = world.process_player_command(player_id, PlayerCommand::ChangeMovementDirection(Some(Direction::Right)));
+                world.apply_events(&command_events);
+                events.extend(command_events);
+            }
+            if !paused(tick)
+            {
+                let update_events = world.update(elapsed_seconds);
+                world.apply_events(&update_events);
+                events.extend(update_events);
+            }
+
+            recorder.record_tick(tick, elapsed_seconds, &events);
+            live.push(describe(&world.get_snapshot()));
+        }
+        recorder.stop();
+
+        let files = replay_files(directory).unwrap();
+        assert_eq!(files.len(), 1);
+        (Replay::load(files[0].to_str().unwrap()).unwrap(), live)
+    }
+
+    #[test]
+    fn recorded_matches_play_back_like_they_were_played()
+    {
+        let directory = temp_directory("recording");
+        let (replay, live) = record_match(&directory);
+        assert_eq!(replay.first_tick(), 0);
+        assert_eq!(replay.last_tick(), TICKS);
+        assert_eq!(replay.header.tick_rate, 50);
+
+        let mut playback = Playback::new(replay);
+        for tick in 1..TICKS + 1
+        {
+            playback.advance();
+            assert_eq!(playback.tick(), tick);
+
+            let (x, match_time) = describe(&playback.snapshot());
+            assert_eq!(x, live[tick as usize].0);
+            assert!((match_time - live[tick as usize].1).abs() < 1e-4, "tick {}: {} != {}", tick, match_time, live[tick as usize].1);
+        }
+        assert!(playback.finished());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn seeking_lands_on_the_same_world_in_any_order()
+    {
+        let directory = temp_directory("seeking");
+        let (replay, live) = record_match(&directory);
+        assert!(replay.keyframe_count() >= 3);
+
+        let mut playback = Playback::new(replay);
+        // 19 is played from the keyframe at 10, across the pause
+        for &tick in [19, 5, 30, 11, 40, 15, 1].iter()
+        {
+            playback.seek(tick);
+            assert_eq!(playback.tick(), tick);
+
+            let (x, match_time) = describe(&playback.snapshot());
+            assert_eq!(x, live[tick as usize].0);
+            assert!((match_time - live[tick as usize].1).abs() < 1e-4, "tick {}: {} != {}", tick, match_time, live[tick as usize].1);
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn replays_with_an_invalid_tick_rate_are_rejected()
+    {
+        let directory = temp_directory("tick_rate");
+        fs::create_dir_all(&directory).unwrap();
+        let path = format!("{}/broken.vpr", directory);
+
+        let header = ReplayHeader
+        {
+            format_version: REPLAY_FORMAT_VERSION,
+            protocol_version: PROTOCOL_VERSION,
+            tick_rate: 0,
+            level: "arena".to_string(),
+            game_mode: "deathmatch".to_string(),
+            started_at: 0
+        };
+        {
+            let mut file = File::create(&path).unwrap();
+            for record in vec![ReplayRecord::Header(header), ReplayRecord::Keyframe(0, World::new(Rules::default()).get_snapshot())]
+            {
+                let encoded = encode(&record, SizeLimit::Infinite).unwrap();
+                file.write_u32::<BigEndian>(encoded.len() as u32).unwrap();
+                file.write_all(&encoded).unwrap();
+            }
+        }
+
+        assert!(Replay::load(&path).is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use vp_shared::PlayerId;
+
+pub const MAX_NAME_LENGTH: usize = 16;
+
+/// Limits how often a player can change its name.
+pub struct RenameCooldown
+{
+    cooldown_ticks: u64,
+    last_rename: HashMap<PlayerId, u64>
+}
+
+impl RenameCooldown
+{
+    pub fn new(cooldown_ticks: u64) -> RenameCooldown
+    {
+        RenameCooldown { cooldown_ticks: cooldown_ticks, last_rename: HashMap::new() }
+    }
+
+    pub fn try_rename(&mut self, player_id: PlayerId, tick: u64) -> bool
+    {
+        let allowed = match self.last_rename.get(&player_id)
+        {
+            Some(&last_tick) => tick >= last_tick + self.cooldown_ticks,
+            None => true
+        };
+
+        if allowed
+        {
+            self.last_rename.insert(player_id, tick);
+        }
+
+        allowed
+    }
+
+    pub fn forget(&mut self, player_id: PlayerId)
+    {
+        self.last_rename.remove(&player_id);
+    }
+}
+
+/// Names are trimmed and may contain letters, digits, spaces, '-' and '_'.
+pub fn validate_name(name: &str) -> Result<String, String>
+{
+    let name = name.trim();
+
+    let length = name.chars().count();
+    if length == 0 || length > MAX_NAME_LENGTH
+    {
+        return Err(format!("Names must be 1 to {} characters long", MAX_NAME_LENGTH));
+    }
+
+    match name.chars().find(|&c| !(c.is_alphanumeric() || c == ' ' || c == '-' || c == '_'))
+    {
+        Some(c) => Err(format!("'{}' is not allowed in names", c)),
+        None => Ok(name.to_string())
+    }
+}
+
+/// Appends a number to a name that is already taken, names are compared ignoring case.
+pub fn deduplicate_name(name: &str, taken_names: &[String]) -> String
+{
+    let is_taken = |candidate: &str| taken_names.iter().any(|taken| taken.to_lowercase() == candidate.to_lowercase());
+
+    if !is_taken(name)
+    {
+        return name.to_string();
+    }
+
+    let mut number = 2;
+    loop
+    {
+        let suffix = number.to_string();
+        let base: String = name.chars().take(MAX_NAME_LENGTH - suffix.len()).collect();
+        let candidate = format!("{}{}", base.trim_right(), suffix);
+        if !is_taken(&candidate)
+        {
+            return candidate;
+        }
+
+        number += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{RenameCooldown, validate_name, deduplicate_name, MAX_NAME_LENGTH};
+
+    fn names(names: &[&str]) -> Vec<String>
+    {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn valid_names_are_trimmed()
+    {
+        assert_eq!(validate_name("  Player One "), Ok("Player One".to_string()));
+        assert_eq!(validate_name("dash-and_under 1"), Ok("dash-and_under 1".to_string()));
+        assert_eq!(validate_name("Jörð"), Ok("Jörð".to_string()));
+    }
+
+    #[test]
+    fn empty_and_long_names_are_rejected()
+    {
+        assert!(validate_name("").is_err());
+        assert!(validate_name("   ").is_err());
+
+        let longest: String = (0..MAX_NAME_LENGTH).map(|_| 'a').collect();
+        assert!(validate_name(&longest).is_ok());
+        assert!(validate_name(&format!("{}a", longest)).is_err());
+
+        // length is counted in characters, not bytes
+        let longest_multibyte: String = (0..MAX_NAME_LENGTH).map(|_| 'ö').collect();
+        assert!(validate_name(&longest_multibyte).is_ok());
+    }
+
+    #[test]
+    fn control_characters_and_symbols_are_rejected()
+    {
+        for name in ["Tab\there", "New\nline", "Bell\u{7}", "Null\u{0}", "<script>", "a/b", "Zero\u{200b}width"].iter()
+        {
+            assert!(validate_name(name).is_err(), "{:?} should be rejected", name);
+        }
+    }
+
+    #[test]
+    fn free_names_are_kept()
+    {
+        assert_eq!(deduplicate_name("Player", &names(&["Someone"])), "Player");
+        assert_eq!(deduplicate_name("Player", &[]), "Player");
+    }
+
+    #[test]
+    fn taken_names_get_the_first_free_number()
+    {
+        assert_eq!(deduplicate_name("player", &names(&["Player"])), "player2");
+        assert_eq!(deduplicate_name("Player", &names(&["Player", "Player2", "PLAYER3"])), "Player4");
+        assert_eq!(deduplicate_name("Bot", &names(&["Bot", "Bot3"])), "Bot2");
+    }
+
+    #[test]
+    fn numbered_names_stay_within_the_length_limit()
+    {
+        let taken: Vec<String> = vec!["ABCDEFGHIJKLMNOP".to_string()]
+            .into_iter()
+            .chain((2..10).map(|number| format!("ABCDEFGHIJKLMNO{}", number)))
+            .collect();
+
+        assert_eq!(deduplicate_name("ABCDEFGHIJKLMNOP", &taken[..1]), "ABCDEFGHIJKLMNO2");
+        assert_eq!(deduplicate_name("ABCDEFGHIJKLMNOP", &taken), "ABCDEFGHIJKLMN10");
+
+        // a space left at the end of the shortened name is dropped
+        assert_eq!(deduplicate_name("ABCDEFGHIJKLMN P", &names(&["ABCDEFGHIJKLMN P"])), "ABCDEFGHIJKLMN2");
+    }
+
+    #[test]
+    fn renames_have_a_cooldown()
+    {
+        let mut cooldown = RenameCooldown::new(10);
+        assert!(cooldown.try_rename(1, 100));
+        assert!(!cooldown.try_rename(1, 109));
+        assert!(cooldown.try_rename(2, 109));
+        assert!(cooldown.try_rename(1, 110));
+
+        cooldown.forget(1);
+        assert!(cooldown.try_rename(1, 111));
+    }
+}
@@ -0,0 +1,292 @@
+use std::io;
+use std::io::{BufWriter, Write};
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::thread::JoinHandle;
+use std::sync::mpsc::{channel, Sender, Receiver};
+
+use time;
+use bincode::SizeLimit;
+use bincode::rustc_serialize::encode;
+use byteorder::{WriteBytesExt, BigEndian};
+
+use vp_shared::{Event, PROTOCOL_VERSION};
+use vp_shared::replay::{ReplayHeader, ReplayRecord, REPLAY_FORMAT_VERSION, REPLAY_FILE_EXTENSION};
+use vp_shared::simulation::Rules;
+
+use vp_world::World;
+
+pub struct RecordingSettings
+{
+    pub directory: String,
+    pub tick_rate: u32,
+    pub keyframe_ticks: u64,
+    pub max_file_bytes: u64,
+    pub keep_files: usize
+}
+
+enum RecorderMessage
+{
+    NewMatch(u64, String, String, Vec<Event>),
+    Tick(u64, f32, Vec<Event>),
+    Stop
+}
+
+/// Writes the event stream of the world to replay files. Writing happens on a thread of its own
+/// that keeps a copy of the world for the keyframes, so the game loop only pays for sending events.
+pub struct Recorder
+{
+    sender: Sender<RecorderMessage>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl Recorder
+{
+    pub fn start(settings: RecordingSettings, rules: Rules) -> Recorder
+    {
+        let (sender, receiver) = channel();
+        let thread = thread::spawn(move ||
+        {
+            let mut writer = ReplayWriter::new(settings, rules);
+            writer.run(receiver);
+        });
+
+        Recorder { sender: sender, thread: Some(thread) }
+    }
+
+    /// Starts a new file, the snapshot is the world the match starts from.
+    pub fn new_match(&self, tick: u64, level: &str, game_mode: &str, snapshot: Vec<Event>)
+    {
+        self.send(RecorderMessage::NewMatch(tick, level.to_string(), game_mode.to_string(), snapshot));
+    }
+
+    /// The elapsed match time keeps the copy of the world in step, so keyframes get the right stats.
+    pub fn record_tick(&self, tick: u64, elapsed_seconds: f32, events: &[Event])
+    {
+        if events.len() != 0 || elapsed_seconds != 0.0
+        {
+            self.send(RecorderMessage::Tick(tick, elapsed_seconds, events.to_vec()));
+        }
+    }
+
+    /// Flushes what was recorded and waits for the writer to finish.
+    pub fn stop(&mut self)
+    {
+        self.send(RecorderMessage::Stop);
+        if let Some(thread) = self.thread.take()
+        {
+            thread.join().ok().expect("Replay writer thread panicked");
+        }
+    }
+
+    fn send(&self, message: RecorderMessage)
+    {
+        if self.sender.send(message).is_err()
+        {
+            debug!("Replay writer is gone, dropping recording");
+        }
+    }
+}
+
+struct ReplayWriter
+{
+    settings: RecordingSettings,
+    world: World,
+    level: String,
+    game_mode: String,
+    file: Option<BufWriter<File>>,
+    file_bytes: u64,
+    last_keyframe_tick: u64,
+    /// Match time of the ticks since the last record, written with the next one.
+    unrecorded_seconds: f32
+}
+
+impl ReplayWriter
+{
+    fn new(settings: RecordingSettings, rules: Rules) -> ReplayWriter
+    {
+        ReplayWriter
+        {
+            settings: settings,
+            world: World::new(rules),
+            level: String::new(),
+            game_mode: String::new(),
+            file: None,
+            file_bytes: 0,
+            last_keyframe_tick: 0,
+            unrecorded_seconds: 0.0
+        }
+    }
+
+    fn run(&mut self, receiver: Receiver<RecorderMessage>)
+    {
+        for message in receiver.iter()
+        {
+            match message
+            {
+                RecorderMessage::NewMatch(tick, level, game_mode, snapshot) =>
+                {
+                    self.world.apply_events(&snapshot);
+                    self.level = level;
+                    self.game_mode = game_mode;
+                    self.open_file(tick);
+                },
+                RecorderMessage::Tick(tick, elapsed_seconds, events) =>
+                {
+                    self.world.advance_time(elapsed_seconds);
+                    self.world.apply_events(&events);
+                    self.unrecorded_seconds += elapsed_seconds;
+                    if events.len() != 0
+                    {
+                        self.write_tick(tick, events);
+                    }
+                },
+                RecorderMessage::Stop => break
+            }
+        }
+
+        self.close_file();
+    }
+
+    fn write_tick(&mut self, tick: u64, events: Vec<Event>)
+    {
+        if self.file.is_none()
+        {
+            return;
+        }
+
+        let elapsed_seconds = self.unrecorded_seconds;
+        self.unrecorded_seconds = 0.0;
+        let mut result = self.write_record(&ReplayRecord::Tick(tick, elapsed_seconds, events));
+        if result.is_ok() && tick >= self.last_keyframe_tick + self.settings.keyframe_ticks
+        {
+            result = self.write_keyframe(tick);
+        }
+
+        match result.and_then(|_| self.file.as_mut().unwrap().flush())
+        {
+            Ok(_) => if self.file_bytes >= self.settings.max_file_bytes
+            {
+                self.open_file(tick);
+            },
+            Err(e) =>
+            {
+                error!("Failed to write replay, recording stops until the next match, {}", e);
+                self.file = None;
+            }
+        }
+    }
+
+    fn open_file(&mut self, tick: u64)
+    {
+        self.close_file();
+
+        let started_at = time::get_time().sec;
+        let path = Path::new(&self.settings.directory).join(format!("replay-{:010}-{:010}.{}", started_at, tick, REPLAY_FILE_EXTENSION));
+        let header = ReplayHeader
+        {
+            format_version: REPLAY_FORMAT_VERSION,
+            protocol_version: PROTOCOL_VERSION,
+            tick_rate: self.settings.tick_rate,
+            level: self.level.clone(),
+            game_mode: self.game_mode.clone(),
+            started_at: started_at
+        };
+
+        match self.start_file(&path, header, tick)
+        {
+            Ok(_) => info!("Recording replay to {}", path.display()),
+            Err(e) =>
+            {
+                error!("Failed to start replay {}, {}", path.display(), e);
+                self.file = None;
+            }
+        }
+
+        if let Err(e) = remove_old_files(&self.settings.directory, self.settings.keep_files)
+        {
+            warn!("Failed to remove old replays from {}, {}", self.settings.directory, e);
+        }
+    }
+
+    fn start_file(&mut self, path: &Path, header: ReplayHeader, tick: u64) -> io::Result<()>
+    {
+        try!(fs::create_dir_all(&self.settings.directory));
+        let file = try!(File::create(path));
+        self.file = Some(BufWriter::new(file));
+        self.file_bytes = 0;
+
+        try!(self.write_record(&ReplayRecord::Header(header)));
+        try!(self.write_keyframe(tick));
+        self.file.as_mut().unwrap().flush()
+    }
+
+    fn close_file(&mut self)
+    {
+        if let Some(mut file) = self.file.take()
+        {
+            if let Err(e) = file.flush()
+            {
+                error!("Failed to finish replay, {}", e);
+            }
+        }
+    }
+
+    /// A keyframe carries the match time, so time before it doesn't need to be recorded.
+    fn write_keyframe(&mut self, tick: u64) -> io::Result<()>
+    {
+        self.last_keyframe_tick = tick;
+        self.unrecorded_seconds = 0.0;
+        let snapshot = self.world.get_snapshot();
+        self.write_record(&ReplayRecord::Keyframe(tick, snapshot))
+    }
+
+    fn write_record(&mut self, record: &ReplayRecord) -> io::Result<()>
+    {
+        let encoded = encode(record, SizeLimit::Infinite).unwrap();
+        {
+            let file = self.file.as_mut().unwrap();
+            try!(file.write_u32::<BigEndian>(encoded.len() as u32));
+            try!(file.write_all(&encoded));
+        }
+
+        self.file_bytes += 4 + encoded.len() as u64;
+        Ok(())
+    }
+}
+
+/// Replay names start with the time they were started at, so sorting them puts the oldest first.
+pub fn replay_files(directory: &str) -> io::Result<Vec<PathBuf>>
+{
+    let mut paths = Vec::new();
+    for entry in try!(fs::read_dir(directory))
+    {
+        let path = try!(entry).path();
+        if path.extension().and_then(|extension| extension.to_str()) == Some(REPLAY_FILE_EXTENSION)
+        {
+            paths.push(path);
+        }
+    }
+
+    paths.sort();
+    Ok(paths)
+}
+
+fn remove_old_files(directory: &str, keep_files: usize) -> io::Result<()>
+{
+    let paths = try!(replay_files(directory));
+    if paths.len() <= keep_files
+    {
+        return Ok(());
+    }
+
+    for path in paths[..paths.len() - keep_files].iter()
+    {
+        info!("Removing old replay {}", path.display());
+        try!(fs::remove_file(path));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,202 @@
+use std::fs;
+use std::io::Read;
+use std::fs::File;
+use std::time::SystemTime;
+
+use toml;
+
+use vp_shared::simulation::Rules;
+
+const CHECK_INTERVAL_TICKS: u32 = 50;
+
+pub struct RulesWatcher
+{
+    path: String,
+    /// Environment and command line rules, they win over the file like they did at startup.
+    overrides: Vec<(String, String)>,
+    last_modified: Option<SystemTime>,
+    ticks_until_check: u32
+}
+
+impl RulesWatcher
+{
+    pub fn new(path: &str, overrides: Vec<(String, String)>) -> RulesWatcher
+    {
+        RulesWatcher { path: path.to_string(), overrides: overrides, last_modified: modified_time(path), ticks_until_check: CHECK_INTERVAL_TICKS }
+    }
+
+    pub fn poll(&mut self, current_rules: &Rules) -> Option<Result<Rules, String>>
+    {
+        if self.ticks_until_check > 0
+        {
+            self.ticks_until_check -= 1;
+            return None;
+        }
+        self.ticks_until_check = CHECK_INTERVAL_TICKS;
+
+        let modified = modified_time(&self.path);
+        if modified == self.last_modified
+        {
+            return None;
+        }
+        self.last_modified = modified;
+
+        info!("Rules file {} changed, reloading...", self.path);
+        Some(load_rules(&self.path, current_rules, &self.overrides))
+    }
+}
+
+/// Applies the rules in the file over `base_rules`, then the `overrides` over those.
+pub fn load_rules(path: &str, base_rules: &Rules, overrides: &[(String, String)]) -> Result<Rules, String>
+{
+    let mut contents = String::new();
+    try!(File::open(path).and_then(|mut file| file.read_to_string(&mut contents)).map_err(|e| format!("failed to read {}: {}", path, e)));
+
+    let mut parser = toml::Parser::new(&contents);
+    let table = match parser.parse()
+    {
+        Some(table) => table,
+        None =>
+        {
+            let errors: Vec<String> = parser.errors.iter().map(|e| e.desc.clone()).collect();
+            return Err(format!("failed to parse {}: {}", path, errors.join("; ")));
+        }
+    };
+
+    let mut rules = *base_rules;
+    for (key, value) in table.iter()
+    {
+        let value = match *value
+        {
+            toml::Value::String(ref s) => s.clone(),
+            ref other => other.to_string()
+        };
+
+        try!(apply_rule(&mut rules, key, &value));
+    }
+
+    for &(ref key, ref value) in overrides
+    {
+        try!(apply_rule(&mut rules, key, value));
+    }
+
+    try!(validate_rules(&rules));
+
+    Ok(rules)
+}
+
+pub fn is_rule(key: &str) -> bool
+{
+    match key
+    {
+        "player_speed" | "player_max_health" | "weapon_damage" | "weapon_range"
+        | "fire_cooldown_seconds" | "respawn_seconds" => true,
+        _ => false
+    }
+}
+
+pub fn apply_rule(rules: &mut Rules, key: &str, value: &str) -> Result<(), String>
+{
+    let rule = match key
+    {
+        "player_speed"          => &mut rules.player_speed,
+        "player_max_health"     => &mut rules.player_max_health,
+        "weapon_damage"         => &mut rules.weapon_damage,
+        "weapon_range"          => &mut rules.weapon_range,
+        "fire_cooldown_seconds" => &mut rules.fire_cooldown_seconds,
+        "respawn_seconds"       => &mut rules.respawn_seconds,
+        _ => return Err(format!("unknown rule {}", key))
+    };
+
+    *rule = try!(value.parse().map_err(|_| format!("invalid value '{}' for {}, expected a number", value, key)));
+
+    Ok(())
+}
+
+pub fn validate_rules(rules: &Rules) -> Result<(), String>
+{
+    let positive = [
+        ("player_speed", rules.player_speed),
+        ("player_max_health", rules.player_max_health),
+        ("weapon_damage", rules.weapon_damage),
+        ("weapon_range", rules.weapon_range),
+    ];
+    for &(key, value) in positive.iter()
+    {
+        if !value.is_finite() || value <= 0.0
+        {
+            return Err(format!("{} must be a positive number, got {}", key, value));
+        }
+    }
+
+    let non_negative = [
+        ("fire_cooldown_seconds", rules.fire_cooldown_seconds),
+        ("respawn_seconds", rules.respawn_seconds),
+    ];
+    for &(key, value) in non_negative.iter()
+    {
+        if !value.is_finite() || value < 0.0
+        {
+            return Err(format!("{} must be zero or a positive number, got {}", key, value));
+        }
+    }
+
+    Ok(())
+}
+
+fn modified_time(path: &str) -> Option<SystemTime>
+{
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    use time;
+
+    use vp_shared::simulation::Rules;
+    use super::load_rules;
+
+    fn rules_file(contents: &str) -> String
+    {
+        let path = env::temp_dir().join(format!("vp_rules_{}.toml", time::precise_time_ns()));
+        let path = path.to_str().unwrap().to_string();
+        File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+        path
+    }
+
+    #[test]
+    fn overrides_win_over_the_file()
+    {
+        let path = rules_file("weapon_damage = 40.0\nrespawn_seconds = 1.0\n");
+        let overrides = vec![("weapon_damage".to_string(), "60".to_string())];
+
+        let rules = load_rules(&path, &Rules::default(), &overrides).unwrap();
+        assert_eq!(rules.weapon_damage, 60.0);
+        assert_eq!(rules.respawn_seconds, 1.0);
+        assert_eq!(rules.player_speed, Rules::default().player_speed);
+
+        // an edit to an overridden rule doesn't undo the override on reload
+        File::create(&path).unwrap().write_all(b"weapon_damage = 10.0\nrespawn_seconds = 2.0\n").unwrap();
+        let reloaded = load_rules(&path, &rules, &overrides).unwrap();
+        assert_eq!(reloaded.weapon_damage, 60.0);
+        assert_eq!(reloaded.respawn_seconds, 2.0);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected()
+    {
+        let path = rules_file("weapon_range = -1.0\n");
+        assert!(load_rules(&path, &Rules::default(), &[]).is_err());
+        assert!(load_rules(&path, &Rules::default(), &[("weapon_range".to_string(), "5".to_string())]).is_ok());
+        assert!(load_rules(&path, &Rules::default(), &[("weapon_range".to_string(), "far".to_string())]).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
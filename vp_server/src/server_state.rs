@@ -0,0 +1,142 @@
+use std::net::SocketAddr;
+use std::collections::{HashMap, HashSet};
+
+use time;
+
+use game_server::Frame;
+use game_server::network_loop::{NetworkEvent, ClientId};
+use config::{ServerConfig, GameMode};
+use vp_shared::{Event, PlayerId, TeamId};
+
+use sessions::Sessions;
+use spectators::SpectatorFeed;
+use profiles::RenameCooldown;
+use chat::Chat;
+use stats_store::{StatsStore, MatchResult};
+use vp_world::World;
+
+pub struct ServerState
+{
+    pub level: String,
+    pub game_mode: GameMode,
+    pub paused: bool,
+    pub client_addresses: HashMap<ClientId, SocketAddr>,
+    /// Last measured round trip of each client, in milliseconds.
+    pub pings: HashMap<ClientId, u32>,
+    pub sessions: Sessions,
+    pub bots: HashSet<PlayerId>,
+    pub spectators: SpectatorFeed,
+    pub renames: RenameCooldown,
+    pub chat: Chat,
+    pub stats: StatsStore,
+    /// Results of players that left before the match ended.
+    departed: Vec<MatchResult>
+}
+
+impl ServerState
+{
+    pub fn new(config: &ServerConfig, stats: StatsStore) -> ServerState
+    {
+        ServerState
+        {
+            level: config.level.clone(),
+            game_mode: config.game_mode,
+            paused: false,
+            client_addresses: HashMap::new(),
+            pings: HashMap::new(),
+            sessions: Sessions::new(config.session_grace_seconds as u64 * config.tick_rate as u64),
+            bots: HashSet::new(),
+            spectators: SpectatorFeed::new(config.spectator_delay_seconds as u64 * config.tick_rate as u64, config.rules),
+            renames: RenameCooldown::new(config.rename_cooldown_seconds as u64 * config.tick_rate as u64),
+            chat: Chat::new(config.tick_rate),
+            stats: stats,
+            departed: Vec::new()
+        }
+    }
+
+    pub fn team_for_new_player(&self, world: &World) -> Option<TeamId>
+    {
+        match self.game_mode
+        {
+            GameMode::Deathmatch => None,
+            GameMode::TeamDeathmatch => Some(world.smallest_team(2))
+        }
+    }
+
+    /// Clears everything kept about a player that left for good, call it before removing the player
+    /// from the world so its stats make it into the match results.
+    pub fn forget_player(&mut self, world: &World, player_id: PlayerId)
+    {
+        if !self.bots.contains(&player_id)
+        {
+            self.departed.extend(match_result(world, player_id));
+        }
+
+        self.bots.remove(&player_id);
+        self.renames.forget(player_id);
+        self.chat.forget(player_id);
+    }
+
+    /// Records the results of the current match, bots are left out.
+    pub fn finish_match(&mut self, world: &World)
+    {
+        let mut players: Vec<MatchResult> = self.departed.drain(..).collect();
+        for (player_id, _) in world.player_states()
+        {
+            if !self.bots.contains(&player_id)
+            {
+                players.extend(match_result(world, player_id));
+            }
+        }
+
+        if players.is_empty()
+        {
+            return;
+        }
+
+        info!("Recording match results of {} players", players.len());
+        let game_mode = self.game_mode.to_string();
+        if let Err(e) = self.stats.record_match(&self.level, &game_mode, world.match_time(), players)
+        {
+            error!("Failed to save match results, {}", e);
+        }
+    }
+
+    pub fn ping_events(&self) -> Vec<(ClientId, Event)>
+    {
+        let now = time::precise_time_ns();
+        self.client_addresses.keys().map(|client_id| (*client_id, Event::Ping(now))).collect()
+    }
+
+    pub fn record_pong(&mut self, client_id: ClientId, pinged_at: u64)
+    {
+        let round_trip = time::precise_time_ns().saturating_sub(pinged_at) / 1_000_000;
+        self.pings.insert(client_id, round_trip as u32);
+    }
+
+    pub fn track_connections(&mut self, frame: &Frame)
+    {
+        for message in frame.messages.iter()
+        {
+            match message
+            {
+                &NetworkEvent::ClientConnected(client_id, address) => { self.client_addresses.insert(client_id, address); },
+                &NetworkEvent::ClientDisconnected(client_id) =>
+                {
+                    self.client_addresses.remove(&client_id);
+                    self.pings.remove(&client_id);
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+fn match_result(world: &World, player_id: PlayerId) -> Option<MatchResult>
+{
+    match (world.player_name(player_id), world.player_stats(player_id))
+    {
+        (Some(name), Some(stats)) => Some(MatchResult { name: name.to_string(), team: world.player_team(player_id), stats: stats }),
+        _ => None
+    }
+}
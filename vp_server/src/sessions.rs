@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use rand::{Rng, OsRng};
+
+use vp_shared::{PlayerId, SessionToken};
+
+use game_server::network_loop::ClientId;
+
+/// Maps connections to the players they control, and keeps players of dropped connections
+/// around for a grace period so the client can resume them with its session token.
+/// Connections without a session are spectators.
+pub struct Sessions
+{
+    grace_ticks: u64,
+    players: HashMap<ClientId, SessionToken>,
+    sessions: HashMap<SessionToken, Session>,
+    rng: OsRng
+}
+
+struct Session
+{
+    player_id: PlayerId,
+    client_id: Option<ClientId>,
+    detached_tick: u64
+}
+
+#[derive(Debug, PartialEq)]
+pub enum JoinOutcome
+{
+    Created(PlayerId, SessionToken),
+    /// Carries the connection the session was taken from, if it was still attached to one.
+    Resumed(PlayerId, SessionToken, Option<ClientId>),
+    AlreadyJoined
+}
+
+impl Sessions
+{
+    pub fn new(grace_ticks: u64) -> Sessions
+    {
+        Sessions
+        {
+            grace_ticks: grace_ticks,
+            players: HashMap::new(),
+            sessions: HashMap::new(),
+            rng: OsRng::new().ok().expect("Failed to open the OS random number generator")
+        }
+    }
+
+    pub fn player_of(&self, client_id: ClientId) -> Option<PlayerId>
+    {
+        self.players.get(&client_id).map(|token| self.sessions[token].player_id)
+    }
+
+    pub fn connected_players(&self) -> usize
+    {
+        self.players.len()
+    }
+
+    pub fn client_of(&self, player_id: PlayerId) -> Option<ClientId>
+    {
+        self.sessions.values().find(|session| session.player_id == player_id).and_then(|session| session.client_id)
+    }
+
+    /// A known token takes over its player, even from a connection that has not timed out yet.
+    /// That connection is left without a player, the caller is expected to disconnect it.
+    pub fn join<F>(&mut self, client_id: ClientId, token: Option<SessionToken>, allocate_player_id: F) -> JoinOutcome
+        where F: FnOnce() -> PlayerId
+    {
+        if self.players.contains_key(&client_id)
+        {
+            return JoinOutcome::AlreadyJoined;
+        }
+
+        if let Some(token) = token
+        {
+            if let Some(session) = self.sessions.get_mut(&token)
+            {
+                let previous_client_id = session.client_id;
+                if let Some(previous_client_id) = previous_client_id
+                {
+                    self.players.remove(&previous_client_id);
+                }
+
+                session.client_id = Some(client_id);
+                self.players.insert(client_id, token);
+                return JoinOutcome::Resumed(session.player_id, token, previous_client_id);
+            }
+        }
+
+        let token = self.new_token();
+        let player_id = allocate_player_id();
+
+        self.sessions.insert(token, Session { player_id: player_id, client_id: Some(client_id), detached_tick: 0 });
+        self.players.insert(client_id, token);
+        JoinOutcome::Created(player_id, token)
+    }
+
+    pub fn detach(&mut self, client_id: ClientId, tick: u64) -> Option<PlayerId>
+    {
+        self.players.remove(&client_id).map(|token|
+        {
+            let session = self.sessions.get_mut(&token).unwrap();
+            session.client_id = None;
+            session.detached_tick = tick;
+            session.player_id
+        })
+    }
+
+    /// Ends the session for good, e.g. when the player is kicked.
+    pub fn end(&mut self, player_id: PlayerId) -> Option<ClientId>
+    {
+        let token = match self.sessions.iter().find(|&(_, session)| session.player_id == player_id)
+        {
+            Some((token, _)) => *token,
+            None => return None
+        };
+
+        let client_id = self.sessions.remove(&token).unwrap().client_id;
+        if let Some(client_id) = client_id
+        {
+            self.players.remove(&client_id);
+        }
+        client_id
+    }
+
+    pub fn expire(&mut self, tick: u64) -> Vec<PlayerId>
+    {
+        let grace_ticks = self.grace_ticks;
+        let expired: Vec<SessionToken> = self.sessions
+            .iter()
+            .filter(|&(_, session)| session.client_id.is_none() && tick >= session.detached_tick + grace_ticks)
+            .map(|(token, _)| *token)
+            .collect();
+
+        expired
+            .into_iter()
+            .map(|token| self.sessions.remove(&token).unwrap().player_id)
+            .collect()
+    }
+
+    fn new_token(&mut self) -> SessionToken
+    {
+        loop
+        {
+            let token = self.rng.gen();
+            if !self.sessions.contains_key(&token)
+            {
+                return token;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{Sessions, JoinOutcome};
+
+    fn created(sessions: &mut Sessions, client_id: usize, player_id: usize) -> u64
+    {
+        match sessions.join(client_id, None, || player_id)
+        {
+            JoinOutcome::Created(created_id, token) =>
+            {
+                assert_eq!(created_id, player_id);
+                token
+            },
+            outcome => panic!("unexpected {:?}", outcome)
+        }
+    }
+
+    #[test]
+    fn joining_creates_a_player_once()
+    {
+        let mut sessions = Sessions::new(100);
+        created(&mut sessions, 1, 10);
+        assert_eq!(sessions.join(1, None, || 11), JoinOutcome::AlreadyJoined);
+        assert_eq!(sessions.player_of(1), Some(10));
+        assert_eq!(sessions.client_of(10), Some(1));
+        assert_eq!(sessions.connected_players(), 1);
+
+        // an unknown token starts a new session
+        created(&mut sessions, 2, 11);
+        assert!(match sessions.join(3, Some(12345), || 12) { JoinOutcome::Created(12, _) => true, _ => false });
+    }
+
+    #[test]
+    fn detached_players_are_resumed_by_token()
+    {
+        let mut sessions = Sessions::new(100);
+        let token = created(&mut sessions, 1, 10);
+        assert_eq!(sessions.detach(1, 5), Some(10));
+        assert_eq!(sessions.player_of(1), None);
+        assert_eq!(sessions.client_of(10), None);
+
+        assert_eq!(sessions.join(2, Some(token), || 11), JoinOutcome::Resumed(10, token, None));
+        assert_eq!(sessions.player_of(2), Some(10));
+        assert_eq!(sessions.client_of(10), Some(2));
+    }
+
+    #[test]
+    fn resuming_an_attached_session_reports_the_connection_it_was_taken_from()
+    {
+        let mut sessions = Sessions::new(100);
+        let token = created(&mut sessions, 1, 10);
+
+        assert_eq!(sessions.join(2, Some(token), || 11), JoinOutcome::Resumed(10, token, Some(1)));
+        assert_eq!(sessions.player_of(1), None);
+        assert_eq!(sessions.player_of(2), Some(10));
+        assert_eq!(sessions.connected_players(), 1);
+
+        // the old connection closing later must not detach the player from the new one
+        assert_eq!(sessions.detach(1, 5), None);
+        assert_eq!(sessions.client_of(10), Some(2));
+    }
+
+    #[test]
+    fn detached_sessions_expire_after_the_grace_period()
+    {
+        let mut sessions = Sessions::new(10);
+        let token = created(&mut sessions, 1, 10);
+        created(&mut sessions, 2, 11);
+        sessions.detach(1, 20);
+
+        assert_eq!(sessions.expire(29), Vec::<usize>::new());
+        assert_eq!(sessions.expire(30), vec![10]);
+        assert_eq!(sessions.expire(100), Vec::<usize>::new());
+
+        // the token is gone with the session
+        assert!(match sessions.join(3, Some(token), || 12) { JoinOutcome::Created(12, _) => true, _ => false });
+    }
+
+    #[test]
+    fn ending_a_session_frees_its_connection()
+    {
+        let mut sessions = Sessions::new(10);
+        created(&mut sessions, 1, 10);
+        assert_eq!(sessions.end(10), Some(1));
+        assert_eq!(sessions.player_of(1), None);
+        assert_eq!(sessions.end(10), None);
+        created(&mut sessions, 1, 11);
+    }
+}
@@ -0,0 +1,24 @@
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+use libc;
+
+static SHUTDOWN_REQUESTED: AtomicBool = ATOMIC_BOOL_INIT;
+
+pub fn install_shutdown_handler()
+{
+    unsafe
+    {
+        libc::signal(libc::SIGINT, handle_shutdown_signal as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, handle_shutdown_signal as libc::sighandler_t);
+    }
+}
+
+pub fn shutdown_requested() -> bool
+{
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
+
+extern "C" fn handle_shutdown_signal(_signal: libc::c_int)
+{
+    SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+}
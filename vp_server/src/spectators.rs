@@ -0,0 +1,90 @@
+use std::collections::{HashMap, VecDeque};
+
+use vp_shared::{Event, PlayerId, Scoreboard};
+use vp_shared::simulation::Rules;
+
+use game_server::network_loop::ClientId;
+use vp_world::World;
+
+/// What spectators see: the live event stream held back by a delay, replayed into a world of its
+/// own so late joining spectators get a snapshot that matches the delayed stream.
+pub struct SpectatorFeed
+{
+    delay_ticks: u64,
+    /// Tick, match time elapsed and events of every tick not shown yet.
+    pending: VecDeque<(u64, f32, Vec<Event>)>,
+    world: World,
+    following: HashMap<ClientId, PlayerId>
+}
+
+impl SpectatorFeed
+{
+    pub fn new(delay_ticks: u64, rules: Rules) -> SpectatorFeed
+    {
+        SpectatorFeed
+        {
+            delay_ticks: delay_ticks,
+            pending: VecDeque::new(),
+            world: World::new(rules),
+            following: HashMap::new()
+        }
+    }
+
+    /// Queues the events of this tick and returns the ones old enough to be shown.
+    pub fn advance(&mut self, tick: u64, elapsed_seconds: f32, events: &[Event]) -> Vec<Event>
+    {
+        if events.len() != 0 || elapsed_seconds != 0.0
+        {
+            self.pending.push_back((tick, elapsed_seconds, events.to_vec()));
+        }
+
+        let mut released = Vec::new();
+        while self.pending.front().map(|&(event_tick, _, _)| event_tick + self.delay_ticks <= tick).unwrap_or(false)
+        {
+            let (_, elapsed_seconds, events) = self.pending.pop_front().unwrap();
+            self.world.advance_time(elapsed_seconds);
+            self.world.apply_events(&events);
+            released.extend(events.into_iter());
+        }
+
+        released
+    }
+
+    pub fn snapshot(&self) -> Vec<Event>
+    {
+        self.world.get_snapshot()
+    }
+
+    pub fn scoreboard(&self) -> Scoreboard
+    {
+        self.world.scoreboard()
+    }
+
+    /// Returns who the spectator ends up following, unknown players are not followed.
+    pub fn follow(&mut self, client_id: ClientId, player_id: Option<PlayerId>) -> Option<PlayerId>
+    {
+        match player_id
+        {
+            Some(player_id) if self.world.has_player(player_id) =>
+            {
+                self.following.insert(client_id, player_id);
+                Some(player_id)
+            },
+            _ =>
+            {
+                self.following.remove(&client_id);
+                None
+            }
+        }
+    }
+
+    pub fn forget(&mut self, client_id: ClientId)
+    {
+        self.following.remove(&client_id);
+    }
+
+    pub fn following(&self, client_id: ClientId) -> Option<PlayerId>
+    {
+        self.following.get(&client_id).cloned()
+    }
+}
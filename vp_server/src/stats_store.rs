@@ -0,0 +1,340 @@
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write, Seek, SeekFrom};
+use std::fs::{File, OpenOptions};
+use std::collections::{HashMap, HashSet};
+
+use time;
+use rustc_serialize::json;
+
+use vp_shared::{PlayerStats, TeamId};
+
+/// The results of one finished match, stored as a line of JSON.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct MatchRecord
+{
+    pub ended_at: i64,
+    pub level: String,
+    pub game_mode: String,
+    pub duration_seconds: f32,
+    pub players: Vec<MatchResult>
+}
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct MatchResult
+{
+    pub name: String,
+    pub team: Option<TeamId>,
+    pub stats: PlayerStats
+}
+
+/// Stats summed over every recorded match of a player.
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct LifetimeStats
+{
+    pub name: String,
+    pub matches: u32,
+    pub kills: u32,
+    pub deaths: u32,
+    pub assists: u32,
+    pub damage_dealt: f32,
+    pub shots: u32,
+    pub hits: u32,
+    pub time_alive: f32
+}
+
+/// Finished matches are appended to a file and summed up per player when it is loaded, players
+/// are identified by their name ignoring case as there are no accounts.
+pub struct StatsStore
+{
+    path: Option<String>,
+    lifetime: HashMap<String, LifetimeStats>,
+    matches_recorded: u64
+}
+
+impl StatsStore
+{
+    pub fn empty() -> StatsStore
+    {
+        StatsStore { path: None, lifetime: HashMap::new(), matches_recorded: 0 }
+    }
+
+    /// A missing file is an empty store, it gets created when the first match ends.
+    pub fn load(path: &str) -> Result<StatsStore, String>
+    {
+        let mut store = StatsStore::empty();
+
+        match File::open(path)
+        {
+            Ok(file) =>
+            {
+                for (number, line) in BufReader::new(file).lines().enumerate()
+                {
+                    let line = try!(line.map_err(|e| format!("Failed to read stats file {}: {}", path, e)));
+                    if line.trim().len() == 0
+                    {
+                        continue;
+                    }
+
+                    // a crash while appending leaves a partial last line, losing that match is fine
+                    match json::decode::<MatchRecord>(&line)
+                    {
+                        Ok(record) => store.add(&record),
+                        Err(e) => warn!("Skipping invalid match record in {} on line {}: {}", path, number + 1, e)
+                    }
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(format!("Failed to open stats file {}: {}", path, e))
+        }
+
+        info!("Loaded {} matches of {} players from {}", store.matches_recorded, store.lifetime.len(), path);
+        store.path = Some(path.to_string());
+        Ok(store)
+    }
+
+    pub fn record_match(&mut self, level: &str, game_mode: &str, duration_seconds: f32, players: Vec<MatchResult>) -> io::Result<()>
+    {
+        let record = MatchRecord
+        {
+            ended_at: time::get_time().sec,
+            level: level.to_string(),
+            game_mode: game_mode.to_string(),
+            duration_seconds: duration_seconds,
+            players: players
+        };
+        self.add(&record);
+
+        match self.path
+        {
+            Some(ref path) =>
+            {
+                let line = json::encode(&record).unwrap();
+                let mut file = try!(OpenOptions::new().read(true).append(true).create(true).open(path));
+                if !try!(ends_with_newline(&mut file))
+                {
+                    // finish a partial line left by a crash so this match doesn't get lost with it
+                    try!(writeln!(file, ""));
+                }
+                writeln!(file, "{}", line)
+            },
+            None => Ok(())
+        }
+    }
+
+    pub fn lifetime(&self, name: &str) -> Option<&LifetimeStats>
+    {
+        self.lifetime.get(&name.to_lowercase())
+    }
+
+    /// The players with the most kills, fewer deaths break ties.
+    pub fn leaderboard(&self, limit: usize) -> Vec<LifetimeStats>
+    {
+        let mut entries: Vec<&LifetimeStats> = self.lifetime.values().collect();
+        entries.sort_by(|a, b| (b.kills, a.deaths, &a.name).cmp(&(a.kills, b.deaths, &b.name)));
+        entries.into_iter().take(limit).cloned().collect()
+    }
+
+    /// Changes whenever a match is recorded, for noticing that the leaderboard needs a refresh.
+    pub fn matches_recorded(&self) -> u64
+    {
+        self.matches_recorded
+    }
+
+    fn add(&mut self, record: &MatchRecord)
+    {
+        self.matches_recorded += 1;
+
+        // a player that left and came back has several results in one match
+        let mut counted = HashSet::new();
+        for result in record.players.iter()
+        {
+            let key = result.name.to_lowercase();
+            let lifetime = self.lifetime.entry(key.clone()).or_insert(LifetimeStats::new(&result.name));
+            lifetime.name = result.name.clone();
+            lifetime.add(&result.stats);
+            if counted.insert(key)
+            {
+                lifetime.matches += 1;
+            }
+        }
+    }
+}
+
+fn ends_with_newline(file: &mut File) -> io::Result<bool>
+{
+    if try!(file.metadata()).len() == 0
+    {
+        return Ok(true);
+    }
+
+    let mut last = [0; 1];
+    try!(file.seek(SeekFrom::End(-1)));
+    try!(file.read_exact(&mut last));
+    Ok(last[0] == b'\n')
+}
+
+impl LifetimeStats
+{
+    fn new(name: &str) -> LifetimeStats
+    {
+        LifetimeStats
+        {
+            name: name.to_string(),
+            matches: 0,
+            kills: 0,
+            deaths: 0,
+            assists: 0,
+            damage_dealt: 0.0,
+            shots: 0,
+            hits: 0,
+            time_alive: 0.0
+        }
+    }
+
+    fn add(&mut self, stats: &PlayerStats)
+    {
+        self.kills += stats.kills;
+        self.deaths += stats.deaths;
+        self.assists += stats.assists;
+        self.damage_dealt += stats.damage_dealt;
+        self.shots += stats.shots;
+        self.hits += stats.hits;
+        self.time_alive += stats.time_alive;
+    }
+
+    pub fn accuracy(&self) -> f32
+    {
+        if self.shots == 0
+        {
+            0.0
+        }
+        else
+        {
+            self.hits as f32 / self.shots as f32
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    use time;
+    use rustc_serialize::json;
+
+    use vp_shared::PlayerStats;
+    use super::{StatsStore, MatchRecord, MatchResult};
+
+    fn temp_file(name: &str) -> String
+    {
+        let path = env::temp_dir().join(format!("vp_{}_{}.jsonl", name, time::precise_time_ns()));
+        path.to_str().unwrap().to_string()
+    }
+
+    fn result(name: &str, kills: u32, deaths: u32) -> MatchResult
+    {
+        let stats = PlayerStats { kills: kills, deaths: deaths, shots: 4, hits: 2, time_alive: 30.0, .. PlayerStats::default() };
+        MatchResult { name: name.to_string(), team: None, stats: stats }
+    }
+
+    fn names(store: &StatsStore, limit: usize) -> Vec<String>
+    {
+        store.leaderboard(limit).into_iter().map(|entry| entry.name).collect()
+    }
+
+    #[test]
+    fn missing_files_are_empty_stores()
+    {
+        let store = StatsStore::load(&temp_file("missing")).unwrap();
+        assert_eq!(store.matches_recorded(), 0);
+        assert_eq!(store.leaderboard(10).len(), 0);
+    }
+
+    #[test]
+    fn recorded_matches_are_summed_again_on_load()
+    {
+        let path = temp_file("recorded");
+        {
+            let mut store = StatsStore::load(&path).unwrap();
+            store.record_match("arena", "deathmatch", 60.0, vec![result("Alice", 3, 1), result("bob", 1, 3)]).unwrap();
+            // alice left and came back, that is still one match
+            store.record_match("arena", "deathmatch", 60.0, vec![result("alice", 1, 0), result("Alice", 1, 1)]).unwrap();
+            assert_eq!(store.matches_recorded(), 2);
+        }
+
+        let store = StatsStore::load(&path).unwrap();
+        assert_eq!(store.matches_recorded(), 2);
+
+        let alice = store.lifetime("ALICE").unwrap();
+        assert_eq!(alice.name, "Alice");
+        assert_eq!((alice.matches, alice.kills, alice.deaths), (2, 5, 2));
+        assert_eq!((alice.shots, alice.hits), (12, 6));
+        assert_eq!(alice.time_alive, 90.0);
+        assert_eq!(alice.accuracy(), 0.5);
+
+        let bob = store.lifetime("bob").unwrap();
+        assert_eq!((bob.matches, bob.kills, bob.deaths), (1, 1, 3));
+        assert!(store.lifetime("carol").is_none());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_and_truncated_lines_are_skipped()
+    {
+        let path = temp_file("corrupt");
+        let record = MatchRecord
+        {
+            ended_at: 0,
+            level: "arena".to_string(),
+            game_mode: "deathmatch".to_string(),
+            duration_seconds: 60.0,
+            players: vec![result("alice", 2, 1)]
+        };
+        let line = json::encode(&record).unwrap();
+        {
+            let mut file = File::create(&path).unwrap();
+            writeln!(file, "{}", line).unwrap();
+            writeln!(file, "not json at all").unwrap();
+            writeln!(file, "").unwrap();
+            writeln!(file, "{{\"ended_at\": 0}}").unwrap();
+            writeln!(file, "{}", line).unwrap();
+            // the server died halfway through appending a match
+            write!(file, "{}", &line[..line.len() / 2]).unwrap();
+        }
+
+        let mut store = StatsStore::load(&path).unwrap();
+        assert_eq!(store.matches_recorded(), 2);
+        assert_eq!(store.lifetime("alice").unwrap().kills, 4);
+
+        // the next match doesn't get glued to the partial line
+        store.record_match("arena", "deathmatch", 60.0, vec![result("alice", 1, 0)]).unwrap();
+        let store = StatsStore::load(&path).unwrap();
+        assert_eq!(store.matches_recorded(), 3);
+        assert_eq!(store.lifetime("alice").unwrap().kills, 5);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn the_leaderboard_ranks_by_kills_then_deaths_then_name()
+    {
+        let mut store = StatsStore::empty();
+        store.record_match("arena", "deathmatch", 60.0, vec![
+            result("dave", 2, 5),
+            result("alice", 5, 2),
+            result("carol", 2, 1),
+            result("bob", 2, 1),
+            result("erin", 0, 0)
+        ]).unwrap();
+
+        assert_eq!(names(&store, 10), vec!["alice", "bob", "carol", "dave", "erin"]);
+        assert_eq!(names(&store, 2), vec!["alice", "bob"]);
+
+        store.record_match("arena", "deathmatch", 60.0, vec![result("erin", 6, 0)]).unwrap();
+        assert_eq!(names(&store, 1), vec!["erin"]);
+    }
+}
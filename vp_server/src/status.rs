@@ -0,0 +1,134 @@
+use std::sync::{Arc, Mutex};
+
+use time::PreciseTime;
+use rustc_serialize::json;
+
+use vp_shared::{PlayerId, PROTOCOL_VERSION};
+
+use server_state::ServerState;
+use stats_store::LifetimeStats;
+use vp_world::World;
+
+pub type SharedStatus = Arc<Mutex<ServerStatus>>;
+
+const LEADERBOARD_SIZE: usize = 10;
+
+pub struct ServerStatus
+{
+    name: String,
+    start_time: PreciseTime,
+    map: String,
+    game_mode: String,
+    match_phase: String,
+    players: Vec<PlayerStatus>,
+    spectator_count: usize,
+    leaderboard: Vec<LifetimeStats>,
+    /// Matches recorded when the leaderboard was last built, it only changes when a match ends.
+    leaderboard_matches: Option<u64>
+}
+
+#[derive(Clone, RustcEncodable)]
+pub struct PlayerStatus
+{
+    id: PlayerId,
+    name: String,
+    bot: bool,
+    kills: u32,
+    deaths: u32
+}
+
+#[derive(RustcEncodable)]
+struct StatusResponse<'a>
+{
+    name: &'a str,
+    version: &'static str,
+    protocol_version: u32,
+    map: &'a str,
+    game_mode: &'a str,
+    match_phase: &'a str,
+    player_count: usize,
+    spectator_count: usize,
+    players: &'a [PlayerStatus],
+    leaderboard: &'a [LifetimeStats],
+    uptime_seconds: i64
+}
+
+pub fn shared(name: &str) -> SharedStatus
+{
+    Arc::new(Mutex::new(ServerStatus
+    {
+        name: name.to_string(),
+        start_time: PreciseTime::now(),
+        map: String::new(),
+        game_mode: String::new(),
+        match_phase: String::new(),
+        players: Vec::new(),
+        spectator_count: 0,
+        leaderboard: Vec::new(),
+        leaderboard_matches: None
+    }))
+}
+
+impl ServerStatus
+{
+    pub fn update(&mut self, world: &World, state: &ServerState)
+    {
+        self.map = state.level.clone();
+        self.game_mode = state.game_mode.to_string();
+        self.match_phase = (if state.paused { "paused" } else { "running" }).to_string();
+        self.players = world
+            .player_states()
+            .into_iter()
+            .map(|(player_id, _)| PlayerStatus
+            {
+                id: player_id,
+                name: world.player_name(player_id).unwrap_or("").to_string(),
+                bot: state.bots.contains(&player_id),
+                kills: world.player_stats(player_id).map(|stats| stats.kills).unwrap_or(0),
+                deaths: world.player_stats(player_id).map(|stats| stats.deaths).unwrap_or(0)
+            })
+            .collect();
+        self.spectator_count = state.client_addresses.len().saturating_sub(state.sessions.connected_players());
+
+        if self.leaderboard_matches != Some(state.stats.matches_recorded())
+        {
+            self.leaderboard = state.stats.leaderboard(LEADERBOARD_SIZE);
+            self.leaderboard_matches = Some(state.stats.matches_recorded());
+        }
+    }
+
+    pub fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    pub fn map(&self) -> &str
+    {
+        &self.map
+    }
+
+    pub fn player_count(&self) -> usize
+    {
+        self.players.len()
+    }
+
+    pub fn render_json(&self) -> String
+    {
+        let response = StatusResponse
+        {
+            name: &self.name,
+            version: env!("CARGO_PKG_VERSION"),
+            protocol_version: PROTOCOL_VERSION,
+            map: &self.map,
+            game_mode: &self.game_mode,
+            match_phase: &self.match_phase,
+            player_count: self.players.len(),
+            spectator_count: self.spectator_count,
+            players: &self.players,
+            leaderboard: &self.leaderboard,
+            uptime_seconds: self.start_time.to(PreciseTime::now()).num_seconds()
+        };
+
+        json::encode(&response).unwrap()
+    }
+}
@@ -1,30 +1,58 @@
 use na::Vec2;
+use std::cmp;
 use std::collections::HashMap;
 use vp_shared::*;
+use vp_shared::simulation;
+use vp_shared::simulation::Rules;
+use vp_shared::combat;
 
 pub struct World
 {
-    players: HashMap<PlayerId, Player>
+    rules: Rules,
+    players: HashMap<PlayerId, Player>,
+    next_player_id: PlayerId,
+    match_time: f32
 }
 
 struct Player
 {
-    state: PlayerState
+    profile: PlayerProfile,
+    state: PlayerState,
+    /// Stats as of `alive_since`, time alive since then is added when they are read.
+    stats: PlayerStats,
+    alive_since: f32,
+    last_fired_at: Option<f32>,
+    died_at: f32,
+    /// Who damaged this player and when, for handing out assists.
+    recent_damage: Vec<(PlayerId, f32)>
 }
 
 use vp_shared::Event::*;
-use vp_shared::PlayerAction::*;
 
 impl World
 {
-    pub fn new() -> World
+    pub fn new(rules: Rules) -> World
     {
-        World { players: HashMap::new() }
+        World { rules: rules, players: HashMap::new(), next_player_id: 1, match_time: 0.0 }
     }
 
-    pub fn create_player(&self, player_id: PlayerId) -> Vec<Event>
+    /// Player ids are never reused, so a late event can't reach a newer player by mistake.
+    pub fn allocate_player_id(&mut self) -> PlayerId
     {
-        vec![PlayerCreated(player_id, PlayerState {movement_direction: None, position: Vec2::new(0.0, 0.0)})]
+        let player_id = self.next_player_id;
+        self.next_player_id += 1;
+        player_id
+    }
+
+    pub fn create_player(&self, player_id: PlayerId, profile: PlayerProfile) -> Vec<Event>
+    {
+        let state = PlayerState { movement_direction: None, position: spawn_position(), health: self.rules.player_max_health, alive: true };
+        vec![PlayerCreated(player_id, profile, state)]
+    }
+
+    pub fn rename_player(&self, player_id: PlayerId, name: String) -> Vec<Event>
+    {
+        vec![PlayerRenamed(player_id, name)]
     }
 
     pub fn remove_player(&self, player_id: PlayerId) -> Vec<Event>
@@ -32,8 +60,100 @@ impl World
         vec![PlayerRemoved(player_id)]
     }
 
+    pub fn restart_match(&self) -> Vec<Event>
+    {
+        let mut events: Vec<Event> = self.players.keys().flat_map(|player_id| self.remove_player(*player_id)).collect();
+        events.push(MatchRestarted);
+        events.extend(self.players.iter().flat_map(|(player_id, player)| self.create_player(*player_id, player.profile.clone())));
+        events
+    }
+
+    pub fn has_player(&self, player_id: PlayerId) -> bool
+    {
+        self.players.contains_key(&player_id)
+    }
+
+    pub fn player_name(&self, player_id: PlayerId) -> Option<&str>
+    {
+        self.players.get(&player_id).map(|player| &player.profile.name[..])
+    }
+
+    pub fn player_team(&self, player_id: PlayerId) -> Option<TeamId>
+    {
+        self.players.get(&player_id).and_then(|player| player.profile.team)
+    }
+
+    pub fn team_members(&self, team: TeamId) -> Vec<PlayerId>
+    {
+        self.players.iter().filter(|&(_, player)| player.profile.team == Some(team)).map(|(player_id, _)| *player_id).collect()
+    }
+
+    /// The team with the fewest players, for putting new players on.
+    pub fn smallest_team(&self, team_count: TeamId) -> TeamId
+    {
+        (0..team_count).min_by_key(|team| self.team_members(*team).len()).unwrap_or(0)
+    }
+
+    pub fn player_names(&self) -> Vec<String>
+    {
+        self.players.values().map(|player| player.profile.name.clone()).collect()
+    }
+
+    /// Names a player could not rename to, its own name is free to change the case of.
+    pub fn other_player_names(&self, player_id: PlayerId) -> Vec<String>
+    {
+        self.players.iter().filter(|&(id, _)| *id != player_id).map(|(_, player)| player.profile.name.clone()).collect()
+    }
+
+    pub fn player_states(&self) -> Vec<(PlayerId, PlayerState)>
+    {
+        self.players.iter().map(|(player_id, player)| (*player_id, player.state)).collect()
+    }
+
+    pub fn player_stats(&self, player_id: PlayerId) -> Option<PlayerStats>
+    {
+        self.players.get(&player_id).map(|player| player.stats(self.match_time))
+    }
+
+    pub fn match_time(&self) -> f32
+    {
+        self.match_time
+    }
+
+    /// The match clock runs with the simulation, it stands still while the game is paused.
+    pub fn advance_time(&mut self, elapsed_seconds: f32)
+    {
+        self.match_time += elapsed_seconds;
+    }
+
+    pub fn scoreboard(&self) -> Scoreboard
+    {
+        let mut entries: Vec<ScoreboardEntry> = self.players.iter().map(|(player_id, player)|
+        {
+            ScoreboardEntry { player_id: *player_id, name: player.profile.name.clone(), team: player.profile.team, stats: player.stats(self.match_time) }
+        }).collect();
+        entries.sort_by(|a, b| (b.stats.kills, a.stats.deaths, a.player_id).cmp(&(a.stats.kills, b.stats.deaths, b.player_id)));
+
+        Scoreboard { match_time: self.match_time, entries: entries }
+    }
+
+    pub fn rules(&self) -> Rules
+    {
+        self.rules
+    }
+
+    pub fn change_rules(&self, rules: Rules) -> Vec<Event>
+    {
+        vec![RulesChanged(rules)]
+    }
+
     pub fn process_player_command(&self, player_id: PlayerId, command: PlayerCommand) -> Vec<Event>
     {
+        if let PlayerCommand::Fire(direction) = command
+        {
+            return self.fire(player_id, direction);
+        }
+
         match self.players.get(&player_id)
         {
             Some(player) =>
@@ -47,21 +167,84 @@ impl World
         }
     }
 
+    /// Shots are resolved on the server so every client agrees on who got hit.
+    fn fire(&self, shooter_id: PlayerId, direction: Direction) -> Vec<Event>
+    {
+        let shooter = match self.players.get(&shooter_id)
+        {
+            Some(player) if player.state.alive => player,
+            _ => return vec![]
+        };
+
+        if !combat::can_fire(shooter.last_fired_at, self.match_time, &self.rules)
+        {
+            return vec![];
+        }
+
+        let weapon = Weapon::Blaster;
+        let mut events = vec![PlayerActed(shooter_id, PlayerAction::Fired(weapon, direction))];
+
+        let targets: Vec<(PlayerId, Position)> = self.players
+            .iter()
+            .filter(|&(player_id, player)| *player_id != shooter_id && player.state.alive && !self.same_team(shooter, player))
+            .map(|(player_id, player)| (*player_id, player.state.position))
+            .collect();
+
+        if let Some(target_id) = combat::trace_shot(shooter.state.position, direction, &self.rules, &targets)
+        {
+            let target = &self.players[&target_id];
+            let hit = combat::hit(&self.rules, target.state.health);
+            events.push(PlayerDamaged(target_id, shooter_id, hit.damage));
+
+            if hit.lethal
+            {
+                let assists = combat::assists(&target.recent_damage, shooter_id, self.match_time);
+                events.push(PlayerKilled(Kill { victim: target_id, killer: shooter_id, assists: assists, weapon: weapon }));
+            }
+        }
+
+        events
+    }
+
+    fn same_team(&self, a: &Player, b: &Player) -> bool
+    {
+        a.profile.team.is_some() && a.profile.team == b.profile.team
+    }
+
     pub fn update(&self, elapsed_seconds: f32) -> Vec<Event>
     {
-        self.all_players(|player| player.update(elapsed_seconds))
+        let rules = &self.rules;
+        let mut events = self.all_players(|player| player.update(rules, elapsed_seconds));
+
+        let match_time = self.match_time;
+        events.extend(self.all_players(|player|
+        {
+            if !player.state.alive && match_time >= player.died_at + rules.respawn_seconds
+            {
+                vec![PlayerAction::Respawned(spawn_position(), rules.player_max_health)]
+            }
+            else
+            {
+                vec![]
+            }
+        }));
+
+        events
     }
 
     pub fn get_snapshot(&self) -> Vec<Event>
     {
-        self.players.iter().map(|(player_id, player)| Event::PlayerCreated(player_id.clone(), player.state.clone())).collect()
+        let mut snapshot = vec![WorldReset, RulesChanged(self.rules)];
+        snapshot.extend(self.players.iter().map(|(player_id, player)| Event::PlayerCreated(player_id.clone(), player.profile.clone(), player.state.clone())));
+        snapshot.push(ScoreboardUpdated(self.scoreboard()));
+        snapshot
     }
 
     pub fn apply_events(&mut self, events: &[Event])
     {
         for event in events
         {
-            self.apply_event(*event);
+            self.apply_event(event.clone());
         }
     }
 
@@ -69,12 +252,96 @@ impl World
     {
         match event
         {
-            PlayerCreated(player_id, player_state) => { self.players.insert(player_id, Player { state : player_state }); },
+            PlayerCreated(player_id, profile, player_state) =>
+            {
+                self.players.insert(player_id, Player::new(profile, player_state, self.match_time));
+                self.next_player_id = cmp::max(self.next_player_id, player_id + 1);
+            },
+            PlayerJoined(_)                        => {}
+            PlayerRenamed(player_id, name)         =>
+            {
+                self.players.get_mut(&player_id).map(|player| player.profile.name = name);
+            },
             PlayerRemoved(player_id)               => { self.players.remove(&player_id); },
+            Chat(_)                                => {}
             PlayerActed(player_id, player_action)  =>
             {
-                self.players.get_mut(&player_id).map(|player| player.apply_event(player_action));
-            }
+                let match_time = self.match_time;
+                self.players.get_mut(&player_id).map(|player|
+                {
+                    match player_action
+                    {
+                        PlayerAction::Fired(_, _) =>
+                        {
+                            player.stats.shots += 1;
+                            player.last_fired_at = Some(match_time);
+                        },
+                        PlayerAction::Respawned(_, _) => player.alive_since = match_time,
+                        _ => {}
+                    }
+                    player.apply_event(player_action)
+                });
+            },
+            PlayerDamaged(target_id, attacker_id, amount) =>
+            {
+                let match_time = self.match_time;
+                self.players.get_mut(&target_id).map(|target|
+                {
+                    target.state.health = (target.state.health - amount).max(0.0);
+                    target.recent_damage.retain(|&(_, damaged_at)| damaged_at + combat::ASSIST_WINDOW_SECONDS >= match_time);
+                    target.recent_damage.push((attacker_id, match_time));
+                });
+                self.players.get_mut(&attacker_id).map(|attacker|
+                {
+                    attacker.stats.damage_dealt += amount;
+                    attacker.stats.hits += 1;
+                });
+            },
+            PlayerKilled(kill)                     =>
+            {
+                let match_time = self.match_time;
+                self.players.get_mut(&kill.victim).map(|victim|
+                {
+                    victim.stats = victim.stats(match_time);
+                    victim.state.alive = false;
+                    victim.state.health = 0.0;
+                    victim.state.movement_direction = None;
+                    victim.stats.deaths += 1;
+                    victim.died_at = match_time;
+                    victim.recent_damage.clear();
+                });
+                self.players.get_mut(&kill.killer).map(|killer| killer.stats.kills += 1);
+                for assist_id in kill.assists
+                {
+                    self.players.get_mut(&assist_id).map(|assist| assist.stats.assists += 1);
+                }
+            },
+            ScoreboardUpdated(scoreboard)          =>
+            {
+                let match_time = scoreboard.match_time;
+                self.match_time = match_time;
+                for entry in scoreboard.entries
+                {
+                    self.players.get_mut(&entry.player_id).map(|player|
+                    {
+                        player.stats = entry.stats;
+                        player.alive_since = match_time;
+                    });
+                }
+            },
+            MatchRestarted                         => { self.match_time = 0.0; },
+            RulesChanged(rules)                    => { self.rules = rules; },
+            WorldReset                             =>
+            {
+                self.players.clear();
+                self.match_time = 0.0;
+            },
+            ServerShuttingDown                     => {}
+            ConnectionRejected(_)                  => {}
+            SessionStarted(_, _)                   => {}
+            Spectating(_)                          => {}
+            CommandRejected(_)                     => {}
+            Ping(_)                                => {}
         }
     }
 
@@ -95,46 +362,292 @@ impl World
 
 impl Player
 {
+    fn new(profile: PlayerProfile, state: PlayerState, match_time: f32) -> Player
+    {
+        Player
+        {
+            profile: profile,
+            state: state,
+            stats: PlayerStats::default(),
+            alive_since: match_time,
+            last_fired_at: None,
+            died_at: 0.0,
+            recent_damage: Vec::new()
+        }
+    }
+
+    fn stats(&self, match_time: f32) -> PlayerStats
+    {
+        let mut stats = self.stats;
+        if self.state.alive
+        {
+            stats.time_alive += match_time - self.alive_since;
+        }
+        stats
+    }
+
     fn process_command(&self, command: PlayerCommand) -> Vec<PlayerAction>
     {
-        match command
+        simulation::process_command(&self.state, command)
+    }
+
+    fn update(&self, rules: &Rules, elapsed_seconds: f32) -> Vec<PlayerAction>
+    {
+        simulation::update(&self.state, rules, elapsed_seconds)
+    }
+
+    fn apply_event(&mut self, event: PlayerAction)
+    {
+        simulation::apply_action(&mut self.state, event)
+    }
+}
+
+fn spawn_position() -> Position
+{
+    Vec2::new(0.0, 0.0)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use na::Vec2;
+
+    use vp_shared::{Event, PlayerId, PlayerCommand, PlayerAction, PlayerProfile, PlayerState, Direction};
+    use vp_shared::simulation::Rules;
+    use vp_shared::mirror::MirroredWorld;
+    use super::World;
+
+    fn profile(name: &str) -> PlayerProfile
+    {
+        PlayerProfile { name: name.to_string(), team: None }
+    }
+
+    fn state_of(world: &World, player_id: PlayerId) -> PlayerState
+    {
+        world.player_states().into_iter().find(|&(id, _)| id == player_id).unwrap().1
+    }
+
+    /// Applies events the way the server does and sends them to a client.
+    fn step(world: &mut World, mirror: &mut MirroredWorld, events: Vec<Event>)
+    {
+        world.apply_events(&events);
+        mirror.apply_events(0.0, &events);
+    }
+
+    fn assert_mirrored(world: &World, mirror: &MirroredWorld)
+    {
+        let states = world.player_states();
+        assert_eq!(states.len(), mirror.players().len());
+
+        for (player_id, state) in states
+        {
+            let mirrored = mirror.players()[&player_id];
+            assert_eq!(state.movement_direction, mirrored.movement_direction);
+            assert_eq!(state.position, mirrored.position);
+            assert_eq!(state.health, mirrored.health);
+            assert_eq!(state.alive, mirrored.alive);
+        }
+    }
+
+    #[test]
+    fn clients_mirror_the_server_world()
+    {
+        let mut world = World::new(Rules { weapon_damage: 60.0, .. Rules::default() });
+        let mut mirror = MirroredWorld::new();
+
+        // a client that just connected starts from a snapshot
+        let snapshot = world.get_snapshot();
+        step(&mut world, &mut mirror, snapshot);
+        let a = world.allocate_player_id();
+        let b = world.allocate_player_id();
+        let events = world.create_player(a, profile("A"));
+        step(&mut world, &mut mirror, events);
+        let events = world.create_player(b, profile("B"));
+        step(&mut world, &mut mirror, events);
+        assert_eq!(mirror.rules().weapon_damage, 60.0);
+
+        let commands = vec!
+        [
+            (a, PlayerCommand::ChangeMovementDirection(Some(Direction::Up))),
+            (b, PlayerCommand::ChangeMovementDirection(Some(Direction::Down))),
+            (a, PlayerCommand::Fire(Direction::Down)),
+            (b, PlayerCommand::ChangeMovementDirection(None)),
+            (a, PlayerCommand::Fire(Direction::Down)),
+            (a, PlayerCommand::ChangeMovementDirection(Some(Direction::Left)))
+        ];
+
+        for (player_id, command) in commands
         {
-            PlayerCommand::ChangeMovementDirection(direction) =>
+            let events = world.process_player_command(player_id, command);
+            step(&mut world, &mut mirror, events);
+
+            for _ in 0..10
             {
-                if self.state.movement_direction != direction
-                {
-                    vec![ChangedMovementDirection(direction)]
-                }
-                else
-                {
-                    vec![]
-                }
+                world.advance_time(0.05);
+                let events = world.update(0.05);
+                step(&mut world, &mut mirror, events);
             }
+
+            assert_mirrored(&world, &mirror);
         }
+
+        assert!(!state_of(&world, b).alive);
+
+        for _ in 0..70
+        {
+            world.advance_time(0.05);
+            let events = world.update(0.05);
+            step(&mut world, &mut mirror, events);
+        }
+
+        assert!(state_of(&world, b).alive);
+        assert_mirrored(&world, &mirror);
     }
 
-    fn update(&self, elapsed_seconds: f32) -> Vec<PlayerAction>
+    #[test]
+    fn clients_predict_what_the_server_does()
     {
-        let player_speed = 2.0;
+        let mut world = World::new(Rules::default());
+        let mut mirror = MirroredWorld::new();
+        let a = world.allocate_player_id();
+        let events = world.create_player(a, profile("A"));
+        step(&mut world, &mut mirror, events);
+        mirror.apply_events(0.0, &[Event::SessionStarted(a, 1)]);
+
+        let command = PlayerCommand::ChangeMovementDirection(Some(Direction::Right));
+        mirror.predict_command(0.0, command.clone());
 
-        match self.state.movement_direction
+        let mut sent = world.process_player_command(a, command);
+        world.apply_events(&sent);
+        for _ in 0..5
         {
-            Some(direction) =>
-            {
-                let new_position = self.state.position + direction.to_vec2() * player_speed * elapsed_seconds;
+            let events = world.update(0.02);
+            world.apply_events(&events);
+            sent.extend(events);
+        }
 
-                vec![Moved(new_position)]
-            },
-            None => vec![]
+        let server = state_of(&world, a);
+        let predicted = mirror.predicted_state(0.1).unwrap();
+        assert_eq!(predicted.movement_direction, server.movement_direction);
+        assert!((predicted.position.x - server.position.x).abs() < 1e-4);
+        assert_eq!(predicted.position.y, server.position.y);
+
+        // once the server's events arrive they replace the prediction
+        mirror.apply_events(0.1, &sent);
+        assert_eq!(mirror.predicted_state(0.1).unwrap().position, server.position);
+    }
+
+    fn combat_world(positions: &[(f32, f32)]) -> (World, Vec<PlayerId>)
+    {
+        let mut world = World::new(Rules::default());
+        let mut player_ids = Vec::new();
+        for (i, &(x, y)) in positions.iter().enumerate()
+        {
+            let player_id = world.allocate_player_id();
+            let events = world.create_player(player_id, profile(&format!("P{}", i)));
+            world.apply_events(&events);
+            world.apply_events(&[Event::PlayerActed(player_id, PlayerAction::Moved(Vec2::new(x, y)))]);
+            player_ids.push(player_id);
         }
+        (world, player_ids)
     }
 
-    fn apply_event(&mut self, event: PlayerAction)
+    fn fire(world: &mut World, shooter_id: PlayerId, direction: Direction) -> Vec<Event>
     {
-        match event
+        let events = world.process_player_command(shooter_id, PlayerCommand::Fire(direction));
+        world.apply_events(&events);
+        world.advance_time(0.5);
+        events
+    }
+
+    fn kill_in(events: &[Event]) -> Option<(PlayerId, PlayerId, Vec<PlayerId>)>
+    {
+        events.iter().filter_map(|event| match *event
+        {
+            Event::PlayerKilled(ref kill) => Some((kill.victim, kill.killer, kill.assists.clone())),
+            _ => None
+        }).next()
+    }
+
+    #[test]
+    fn kills_are_credited_to_the_killer_and_the_victim()
+    {
+        let (mut world, ids) = combat_world(&[(0.0, 0.0), (0.0, 3.0)]);
+        let (a, b) = (ids[0], ids[1]);
+
+        for _ in 0..3
+        {
+            assert_eq!(kill_in(&fire(&mut world, a, Direction::Up)), None);
+        }
+        assert_eq!(state_of(&world, b).health, 25.0);
+        assert_eq!(kill_in(&fire(&mut world, a, Direction::Up)), Some((b, a, vec![])));
+
+        let killer = world.player_stats(a).unwrap();
+        assert_eq!((killer.kills, killer.deaths, killer.shots, killer.hits), (1, 0, 4, 4));
+        assert_eq!(killer.damage_dealt, 100.0);
+        let victim = world.player_stats(b).unwrap();
+        assert_eq!((victim.kills, victim.deaths), (0, 1));
+        assert!(!state_of(&world, b).alive);
+
+        // dead players are not targets, the shot misses
+        let events = fire(&mut world, a, Direction::Up);
+        assert_eq!(events.len(), 1);
+        assert_eq!(world.player_stats(a).unwrap().hits, 4);
+    }
+
+    #[test]
+    fn shots_respect_the_cooldown()
+    {
+        let (mut world, ids) = combat_world(&[(0.0, 0.0), (0.0, 3.0)]);
+        let events = world.process_player_command(ids[0], PlayerCommand::Fire(Direction::Up));
+        world.apply_events(&events);
+        assert_eq!(events.len(), 2);
+        assert_eq!(world.process_player_command(ids[0], PlayerCommand::Fire(Direction::Up)).len(), 0);
+    }
+
+    #[test]
+    fn recent_damage_earns_an_assist()
+    {
+        let (mut world, ids) = combat_world(&[(0.0, 0.0), (0.0, 3.0), (3.0, 3.0), (0.0, 6.0)]);
+        let (a, b, c, d) = (ids[0], ids[1], ids[2], ids[3]);
+
+        // d's damage is too old by the time b dies, c's is recent
+        fire(&mut world, d, Direction::Down);
+        world.advance_time(6.0);
+        fire(&mut world, c, Direction::Left);
+        fire(&mut world, a, Direction::Up);
+        assert_eq!(kill_in(&fire(&mut world, a, Direction::Up)), Some((b, a, vec![c])));
+
+        assert_eq!(world.player_stats(c).unwrap().assists, 1);
+        assert_eq!(world.player_stats(d).unwrap().assists, 0);
+        assert_eq!(world.player_stats(a).unwrap().assists, 0);
+    }
+
+    #[test]
+    fn the_scoreboard_ranks_by_kills_then_deaths()
+    {
+        let (mut world, ids) = combat_world(&[(0.0, 0.0), (0.0, 3.0), (10.0, 10.0)]);
+        let (a, b, c) = (ids[0], ids[1], ids[2]);
+        for _ in 0..4
         {
-            ChangedMovementDirection(new_direction) => self.state.movement_direction = new_direction,
-            Moved(new_position) => self.state.position = new_position,
+            fire(&mut world, a, Direction::Up);
         }
+
+        let scoreboard = world.scoreboard();
+        assert_eq!(scoreboard.match_time, 2.0);
+        let order: Vec<PlayerId> = scoreboard.entries.iter().map(|entry| entry.player_id).collect();
+        assert_eq!(order, vec![a, c, b]);
+
+        // time alive stops counting at the death and picks up again after the respawn
+        assert_eq!(scoreboard.entries[0].stats.time_alive, 2.0);
+        assert_eq!(scoreboard.entries[2].stats.time_alive, 1.5);
+
+        world.advance_time(3.0);
+        let events = world.update(0.0);
+        world.apply_events(&events);
+        assert!(state_of(&world, b).alive);
+        world.advance_time(1.0);
+        assert_eq!(world.player_stats(b).unwrap().time_alive, 2.5);
+        assert_eq!(world.player_stats(b).unwrap().deaths, 1);
     }
 }
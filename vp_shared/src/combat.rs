@@ -0,0 +1,126 @@
+use {PlayerId, Position, Direction};
+use simulation::Rules;
+
+/// How far from the line of fire a player can stand and still be hit.
+pub const HIT_RADIUS: f32 = 0.5;
+
+/// Damage older than this doesn't count towards an assist.
+pub const ASSIST_WINDOW_SECONDS: f32 = 5.0;
+
+/// What a shot that connects does to its target.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit
+{
+    pub damage: f32,
+    pub lethal: bool
+}
+
+pub fn can_fire(last_fired_at: Option<f32>, match_time: f32, rules: &Rules) -> bool
+{
+    match last_fired_at
+    {
+        Some(last_fired_at) => match_time >= last_fired_at + rules.fire_cooldown_seconds,
+        None => true
+    }
+}
+
+/// Finds the nearest target in the line of fire, if any is in range.
+pub fn trace_shot(origin: Position, direction: Direction, rules: &Rules, targets: &[(PlayerId, Position)]) -> Option<PlayerId>
+{
+    let forward = direction.to_vec2();
+    let mut nearest: Option<(PlayerId, f32)> = None;
+
+    for &(player_id, position) in targets
+    {
+        let offset = position - origin;
+        let distance = offset.x * forward.x + offset.y * forward.y;
+        let miss_distance = (offset.x * forward.y - offset.y * forward.x).abs();
+        if distance < 0.0 || distance > rules.weapon_range || miss_distance > HIT_RADIUS
+        {
+            continue;
+        }
+
+        if nearest.map(|(_, nearest_distance)| distance < nearest_distance).unwrap_or(true)
+        {
+            nearest = Some((player_id, distance));
+        }
+    }
+
+    nearest.map(|(player_id, _)| player_id)
+}
+
+/// Damage never exceeds the health left, so damage dealt adds up to the health taken.
+pub fn hit(rules: &Rules, target_health: f32) -> Hit
+{
+    Hit { damage: rules.weapon_damage.min(target_health), lethal: target_health <= rules.weapon_damage }
+}
+
+/// Everyone but the killer that damaged the victim within the assist window, each once.
+pub fn assists(recent_damage: &[(PlayerId, f32)], killer_id: PlayerId, match_time: f32) -> Vec<PlayerId>
+{
+    let mut assists = Vec::new();
+    for &(attacker_id, damaged_at) in recent_damage.iter()
+    {
+        if attacker_id != killer_id && damaged_at + ASSIST_WINDOW_SECONDS >= match_time && !assists.contains(&attacker_id)
+        {
+            assists.push(attacker_id);
+        }
+    }
+    assists
+}
+
+#[cfg(test)]
+mod tests
+{
+    use na::Vec2;
+
+    use Direction;
+    use simulation::Rules;
+    use super::*;
+
+    #[test]
+    fn shots_hit_the_nearest_target_in_line()
+    {
+        let rules = Rules::default();
+        let targets = vec![(1, Vec2::new(0.0, 6.0)), (2, Vec2::new(0.3, 3.0)), (3, Vec2::new(0.0, -1.0))];
+        assert_eq!(trace_shot(Vec2::new(0.0, 0.0), Direction::Up, &rules, &targets), Some(2));
+        assert_eq!(trace_shot(Vec2::new(0.0, 0.0), Direction::Down, &rules, &targets), Some(3));
+    }
+
+    #[test]
+    fn shots_miss_beyond_range_and_hit_radius()
+    {
+        let rules = Rules { weapon_range: 5.0, .. Rules::default() };
+        let origin = Vec2::new(0.0, 0.0);
+        assert_eq!(trace_shot(origin, Direction::Right, &rules, &[(1, Vec2::new(5.5, 0.0))]), None);
+        assert_eq!(trace_shot(origin, Direction::Right, &rules, &[(1, Vec2::new(2.0, HIT_RADIUS + 0.1))]), None);
+        assert_eq!(trace_shot(origin, Direction::Left, &rules, &[(1, Vec2::new(2.0, 0.0))]), None);
+        assert_eq!(trace_shot(origin, Direction::Right, &rules, &[]), None);
+    }
+
+    #[test]
+    fn fire_cooldown()
+    {
+        let rules = Rules::default();
+        assert!(can_fire(None, 0.0, &rules));
+        assert!(!can_fire(Some(1.0), 1.0 + rules.fire_cooldown_seconds / 2.0, &rules));
+        assert!(can_fire(Some(1.0), 1.0 + rules.fire_cooldown_seconds, &rules));
+    }
+
+    #[test]
+    fn hits_never_deal_more_than_the_health_left()
+    {
+        let rules = Rules::default();
+        assert_eq!(hit(&rules, 100.0), Hit { damage: rules.weapon_damage, lethal: false });
+        assert_eq!(hit(&rules, rules.weapon_damage), Hit { damage: rules.weapon_damage, lethal: true });
+        assert_eq!(hit(&rules, 10.0), Hit { damage: 10.0, lethal: true });
+    }
+
+    #[test]
+    fn assists_exclude_the_killer_and_old_damage()
+    {
+        let recent_damage = vec![(1, 0.0), (2, 8.0), (3, 9.0), (2, 9.5), (4, 10.0)];
+        assert_eq!(assists(&recent_damage, 4, 10.0), vec![2, 3]);
+        assert_eq!(assists(&recent_damage, 4, 20.0), Vec::<usize>::new());
+    }
+}
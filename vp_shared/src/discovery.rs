@@ -0,0 +1,69 @@
+use PROTOCOL_VERSION;
+
+pub const DISCOVERY_PORT: u16 = 8001;
+
+const DISCOVERY_MAGIC: u32 = 0x56504449;
+
+#[derive(Clone, Copy, Debug, RustcEncodable, RustcDecodable)]
+pub struct DiscoveryQuery
+{
+    pub magic: u32,
+    pub protocol_version: u32
+}
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct DiscoveryResponse
+{
+    pub magic: u32,
+    pub protocol_version: u32,
+    pub name: String,
+    pub game_port: u16,
+    pub map: String,
+    pub player_count: u32,
+    pub max_players: u32
+}
+
+impl DiscoveryQuery
+{
+    pub fn new() -> DiscoveryQuery
+    {
+        DiscoveryQuery { magic: DISCOVERY_MAGIC, protocol_version: PROTOCOL_VERSION }
+    }
+
+    pub fn is_valid(&self) -> bool
+    {
+        self.magic == DISCOVERY_MAGIC
+    }
+
+    pub fn is_compatible(&self) -> bool
+    {
+        self.protocol_version == PROTOCOL_VERSION
+    }
+}
+
+impl DiscoveryResponse
+{
+    pub fn new(name: &str, game_port: u16, map: &str, player_count: u32, max_players: u32) -> DiscoveryResponse
+    {
+        DiscoveryResponse
+        {
+            magic: DISCOVERY_MAGIC,
+            protocol_version: PROTOCOL_VERSION,
+            name: name.to_string(),
+            game_port: game_port,
+            map: map.to_string(),
+            player_count: player_count,
+            max_players: max_players
+        }
+    }
+
+    pub fn is_valid(&self) -> bool
+    {
+        self.magic == DISCOVERY_MAGIC
+    }
+
+    pub fn is_compatible(&self) -> bool
+    {
+        self.protocol_version == PROTOCOL_VERSION
+    }
+}
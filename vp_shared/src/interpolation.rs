@@ -0,0 +1,309 @@
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+
+use na;
+
+use {Event, PlayerId, PlayerAction, Position};
+
+#[derive(Clone, Copy, Debug)]
+pub struct InterpolationSettings
+{
+    pub delay_seconds: f64,
+    pub max_extrapolation_seconds: f64,
+    pub teleport_distance: f32,
+    pub max_samples: usize
+}
+
+pub struct InterpolationBuffer
+{
+    samples: VecDeque<Sample>,
+    max_samples: usize
+}
+
+pub struct EntityInterpolator
+{
+    settings: InterpolationSettings,
+    buffers: HashMap<PlayerId, InterpolationBuffer>
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Sample
+{
+    time: f64,
+    position: Position,
+    teleported: bool
+}
+
+impl Default for InterpolationSettings
+{
+    fn default() -> InterpolationSettings
+    {
+        InterpolationSettings
+        {
+            delay_seconds: 0.1,
+            max_extrapolation_seconds: 0.25,
+            teleport_distance: 2.0,
+            max_samples: 32
+        }
+    }
+}
+
+impl InterpolationBuffer
+{
+    /// A buffer always keeps at least the latest sample, so `max_samples` of 0 is treated as 1.
+    pub fn new(max_samples: usize) -> InterpolationBuffer
+    {
+        let max_samples = cmp::max(max_samples, 1);
+        InterpolationBuffer { samples: VecDeque::with_capacity(max_samples), max_samples: max_samples }
+    }
+
+    pub fn push(&mut self, time: f64, position: Position, teleport_distance: f32)
+    {
+        let teleported = match self.samples.back()
+        {
+            Some(last) => na::norm(&(position - last.position)) > teleport_distance,
+            None => true
+        };
+
+        self.push_sample(Sample { time: time, position: position, teleported: teleported });
+    }
+
+    pub fn push_teleport(&mut self, time: f64, position: Position)
+    {
+        self.push_sample(Sample { time: time, position: position, teleported: true });
+    }
+
+    pub fn sample(&self, render_time: f64, max_extrapolation_seconds: f64) -> Option<Position>
+    {
+        let first = match self.samples.front()
+        {
+            Some(first) => *first,
+            None => return None
+        };
+
+        if render_time <= first.time
+        {
+            return Some(first.position);
+        }
+
+        let mut previous = first;
+        for next in self.samples.iter().skip(1)
+        {
+            if render_time < next.time
+            {
+                if next.teleported
+                {
+                    return Some(previous.position);
+                }
+
+                return Some(lerp(previous, *next, render_time));
+            }
+
+            previous = *next;
+        }
+
+        Some(self.extrapolate(render_time, max_extrapolation_seconds))
+    }
+
+    pub fn discard_before(&mut self, time: f64)
+    {
+        while self.samples.len() > 1 && self.samples[1].time <= time
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    fn extrapolate(&self, render_time: f64, max_extrapolation_seconds: f64) -> Position
+    {
+        let count = self.samples.len();
+        let last = self.samples[count - 1];
+
+        if count < 2 || last.teleported
+        {
+            return last.position;
+        }
+
+        let previous = self.samples[count - 2];
+        let capped_time = last.time + (render_time - last.time).min(max_extrapolation_seconds);
+
+        lerp(previous, last, capped_time)
+    }
+
+    fn push_sample(&mut self, sample: Sample)
+    {
+        // samples arriving out of order are dropped, the buffer is always sorted by time
+        if self.samples.back().map_or(false, |last| sample.time < last.time)
+        {
+            return;
+        }
+
+        if self.samples.len() == self.max_samples
+        {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(sample);
+    }
+}
+
+impl EntityInterpolator
+{
+    pub fn new(settings: InterpolationSettings) -> EntityInterpolator
+    {
+        EntityInterpolator { settings: settings, buffers: HashMap::new() }
+    }
+
+    pub fn push_events(&mut self, time: f64, events: &[Event])
+    {
+        for event in events
+        {
+            match *event
+            {
+                Event::PlayerCreated(player_id, _, player_state) => self.push_teleport(player_id, time, player_state.position),
+                Event::PlayerRemoved(player_id) => self.remove(player_id),
+                Event::PlayerActed(player_id, PlayerAction::Moved(position)) => self.push_position(player_id, time, position),
+                Event::PlayerActed(player_id, PlayerAction::Respawned(position, _)) => self.push_teleport(player_id, time, position),
+                Event::WorldReset => self.buffers.clear(),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn push_position(&mut self, player_id: PlayerId, time: f64, position: Position)
+    {
+        let teleport_distance = self.settings.teleport_distance;
+        self.find_buffer(player_id).push(time, position, teleport_distance);
+    }
+
+    pub fn push_teleport(&mut self, player_id: PlayerId, time: f64, position: Position)
+    {
+        self.find_buffer(player_id).push_teleport(time, position);
+    }
+
+    pub fn remove(&mut self, player_id: PlayerId)
+    {
+        self.buffers.remove(&player_id);
+    }
+
+    pub fn position(&self, player_id: PlayerId, now: f64) -> Option<Position>
+    {
+        let render_time = now - self.settings.delay_seconds;
+
+        self.buffers
+            .get(&player_id)
+            .and_then(|buffer| buffer.sample(render_time, self.settings.max_extrapolation_seconds))
+    }
+
+    pub fn positions(&mut self, now: f64) -> Vec<(PlayerId, Position)>
+    {
+        let render_time = now - self.settings.delay_seconds;
+        let max_extrapolation_seconds = self.settings.max_extrapolation_seconds;
+
+        self.buffers
+            .iter_mut()
+            .filter_map(|(player_id, buffer)|
+            {
+                buffer.discard_before(render_time);
+                buffer.sample(render_time, max_extrapolation_seconds).map(|position| (*player_id, position))
+            })
+            .collect()
+    }
+
+    fn find_buffer<'a>(&'a mut self, player_id: PlayerId) -> &'a mut InterpolationBuffer
+    {
+        let max_samples = self.settings.max_samples;
+        self.buffers.entry(player_id).or_insert_with(|| InterpolationBuffer::new(max_samples))
+    }
+}
+
+fn lerp(from: Sample, to: Sample, time: f64) -> Position
+{
+    let span = to.time - from.time;
+    if span <= 0.0
+    {
+        return to.position;
+    }
+
+    let t = ((time - from.time) / span) as f32;
+    from.position + (to.position - from.position) * t
+}
+
+#[cfg(test)]
+mod tests
+{
+    use na::Vec2;
+
+    use super::*;
+
+    #[test]
+    fn samples_between_snapshots_are_interpolated()
+    {
+        let mut buffer = InterpolationBuffer::new(8);
+        buffer.push(1.0, Vec2::new(0.0, 0.0), 2.0);
+        buffer.push(2.0, Vec2::new(1.0, 0.0), 2.0);
+        buffer.push(3.0, Vec2::new(1.0, 1.0), 2.0);
+
+        assert_eq!(buffer.sample(1.5, 0.0), Some(Vec2::new(0.5, 0.0)));
+        assert_eq!(buffer.sample(2.0, 0.0), Some(Vec2::new(1.0, 0.0)));
+        assert_eq!(buffer.sample(2.25, 0.0), Some(Vec2::new(1.0, 0.25)));
+    }
+
+    #[test]
+    fn samples_before_the_first_snapshot_hold_it()
+    {
+        let mut buffer = InterpolationBuffer::new(8);
+        assert_eq!(buffer.sample(1.0, 0.25), None);
+
+        buffer.push(1.0, Vec2::new(3.0, 3.0), 2.0);
+        buffer.push(2.0, Vec2::new(4.0, 3.0), 2.0);
+        assert_eq!(buffer.sample(0.0, 0.25), Some(Vec2::new(3.0, 3.0)));
+    }
+
+    #[test]
+    fn samples_beyond_the_last_snapshot_are_extrapolated_up_to_the_limit()
+    {
+        let mut buffer = InterpolationBuffer::new(8);
+        buffer.push(1.0, Vec2::new(0.0, 0.0), 2.0);
+        buffer.push(2.0, Vec2::new(1.0, 0.0), 2.0);
+
+        assert_eq!(buffer.sample(2.25, 0.5), Some(Vec2::new(1.25, 0.0)));
+        assert_eq!(buffer.sample(10.0, 0.5), Some(Vec2::new(1.5, 0.0)));
+    }
+
+    #[test]
+    fn teleports_are_not_interpolated_or_extrapolated()
+    {
+        let mut buffer = InterpolationBuffer::new(8);
+        buffer.push(1.0, Vec2::new(0.0, 0.0), 2.0);
+        buffer.push(2.0, Vec2::new(10.0, 0.0), 2.0);
+
+        assert_eq!(buffer.sample(1.5, 0.5), Some(Vec2::new(0.0, 0.0)));
+        assert_eq!(buffer.sample(3.0, 0.5), Some(Vec2::new(10.0, 0.0)));
+    }
+
+    #[test]
+    fn a_zero_sized_buffer_keeps_the_latest_sample()
+    {
+        let mut buffer = InterpolationBuffer::new(0);
+        buffer.push(1.0, Vec2::new(0.0, 0.0), 2.0);
+        buffer.push(2.0, Vec2::new(1.0, 0.0), 2.0);
+        assert_eq!(buffer.sample(0.0, 0.0), Some(Vec2::new(1.0, 0.0)));
+
+        let mut interpolator = EntityInterpolator::new(InterpolationSettings { max_samples: 0, .. InterpolationSettings::default() });
+        interpolator.push_position(1, 1.0, Vec2::new(2.0, 2.0));
+        assert_eq!(interpolator.position(1, 5.0), Some(Vec2::new(2.0, 2.0)));
+    }
+
+    #[test]
+    fn interpolator_renders_behind_the_latest_snapshot()
+    {
+        let settings = InterpolationSettings { delay_seconds: 0.5, .. InterpolationSettings::default() };
+        let mut interpolator = EntityInterpolator::new(settings);
+        interpolator.push_teleport(1, 1.0, Vec2::new(0.0, 0.0));
+        interpolator.push_position(1, 2.0, Vec2::new(1.0, 0.0));
+
+        assert_eq!(interpolator.position(1, 2.0), Some(Vec2::new(0.5, 0.0)));
+        assert_eq!(interpolator.positions(2.0), vec![(1, Vec2::new(0.5, 0.0))]);
+        assert_eq!(interpolator.position(2, 2.0), None);
+    }
+}
@@ -1,20 +1,64 @@
 extern crate nalgebra as na;
 extern crate rustc_serialize;
 
+pub mod simulation;
+pub mod combat;
+pub mod interpolation;
+pub mod mirror;
+pub mod discovery;
+pub mod replay;
+
 use na::Vec2;
 
-#[derive(Clone, Copy, Debug, RustcEncodable, RustcDecodable)]
+use simulation::Rules;
+
+pub const PROTOCOL_VERSION: u32 = 2;
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
 pub enum PlayerCommand
 {
-    ChangeMovementDirection(Option<Direction>)
+    Join(Option<SessionToken>, String),
+    Rename(String),
+    Chat(ChatChannel, String),
+    Spectate,
+    Follow(Option<PlayerId>),
+    RequestScoreboard,
+    ChangeMovementDirection(Option<Direction>),
+    Fire(Direction),
+    /// Answers `Event::Ping` with the time it carried.
+    Pong(u64)
 }
 
-#[derive(Clone, Copy, Debug, RustcEncodable, RustcDecodable)]
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
 pub enum Event
 {
-    PlayerCreated(PlayerId, PlayerState),
+    PlayerCreated(PlayerId, PlayerProfile, PlayerState),
+    PlayerJoined(PlayerId),
+    PlayerRenamed(PlayerId, String),
     PlayerRemoved(PlayerId),
-    PlayerActed(PlayerId, PlayerAction)
+    Chat(ChatMessage),
+    PlayerActed(PlayerId, PlayerAction),
+    PlayerDamaged(PlayerId, PlayerId, f32),
+    PlayerKilled(Kill),
+    ScoreboardUpdated(Scoreboard),
+    MatchRestarted,
+    RulesChanged(Rules),
+    WorldReset,
+    ServerShuttingDown,
+    ConnectionRejected(RejectReason),
+    SessionStarted(PlayerId, SessionToken),
+    Spectating(Option<PlayerId>),
+    CommandRejected(String),
+    /// Sent to every client now and then, the time is echoed back to measure the round trip.
+    Ping(u64)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, RustcEncodable, RustcDecodable)]
+pub enum RejectReason
+{
+    ServerFull,
+    Banned,
+    TooManyConnections
 }
 
 pub type Position = Vec2<f32>;
@@ -30,18 +74,113 @@ pub enum Direction
 
 pub type PlayerId = usize;
 
+/// Issued when a client joins, presenting it again resumes the same player.
+pub type SessionToken = u64;
+
+pub type TeamId = u8;
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct PlayerProfile
+{
+    pub name: String,
+    pub team: Option<TeamId>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum ChatChannel
+{
+    All,
+    Team,
+    Whisper(PlayerId)
+}
+
+/// A message without a sender comes from the server.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct ChatMessage
+{
+    pub channel: ChatChannel,
+    pub sender: Option<PlayerId>,
+    pub text: String
+}
+
 #[derive(Clone, Copy, Debug, RustcEncodable, RustcDecodable)]
 pub struct PlayerState
 {
     pub movement_direction: Option<Direction>,
-    pub position: Position
+    pub position: Position,
+    pub health: f32,
+    pub alive: bool
 }
 
 #[derive(Clone, Copy, Debug, RustcEncodable, RustcDecodable)]
 pub enum PlayerAction
 {
     ChangedMovementDirection(Option<Direction>),
-    Moved(Position)
+    Moved(Position),
+    Fired(Weapon, Direction),
+    Respawned(Position, f32)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum Weapon
+{
+    Blaster
+}
+
+/// A kill feed entry, assists are the other players that damaged the victim shortly before.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct Kill
+{
+    pub victim: PlayerId,
+    pub killer: PlayerId,
+    pub assists: Vec<PlayerId>,
+    pub weapon: Weapon
+}
+
+/// Per player statistics for the current match.
+#[derive(Clone, Copy, Debug, Default, RustcEncodable, RustcDecodable)]
+pub struct PlayerStats
+{
+    pub kills: u32,
+    pub deaths: u32,
+    pub assists: u32,
+    pub damage_dealt: f32,
+    pub shots: u32,
+    pub hits: u32,
+    pub time_alive: f32
+}
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct ScoreboardEntry
+{
+    pub player_id: PlayerId,
+    pub name: String,
+    pub team: Option<TeamId>,
+    pub stats: PlayerStats
+}
+
+/// Entries are ordered best first.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct Scoreboard
+{
+    pub match_time: f32,
+    pub entries: Vec<ScoreboardEntry>
+}
+
+impl PlayerStats
+{
+    /// Fraction of shots that hit, zero before the first shot.
+    pub fn accuracy(&self) -> f32
+    {
+        if self.shots == 0
+        {
+            0.0
+        }
+        else
+        {
+            self.hits as f32 / self.shots as f32
+        }
+    }
 }
 
 impl Direction
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use {Event, PlayerId, PlayerState, PlayerCommand};
+use simulation;
+use simulation::Rules;
+
+/// A client's copy of the player states, kept in step by the events the server sends.
+/// The local player is predicted ahead of them with the same simulation the server runs.
+pub struct MirroredWorld
+{
+    rules: Rules,
+    players: HashMap<PlayerId, PlayerState>,
+    local_player: Option<PlayerId>,
+    /// The local player as of `predicted_at`, including commands the server hasn't answered yet.
+    predicted: Option<PlayerState>,
+    predicted_at: f64
+}
+
+impl MirroredWorld
+{
+    pub fn new() -> MirroredWorld
+    {
+        MirroredWorld { rules: Rules::default(), players: HashMap::new(), local_player: None, predicted: None, predicted_at: 0.0 }
+    }
+
+    pub fn rules(&self) -> Rules
+    {
+        self.rules
+    }
+
+    pub fn players(&self) -> &HashMap<PlayerId, PlayerState>
+    {
+        &self.players
+    }
+
+    pub fn local_player(&self) -> Option<PlayerId>
+    {
+        self.local_player
+    }
+
+    /// The server is authoritative, whatever was predicted is replaced by what it sent.
+    pub fn apply_events(&mut self, time: f64, events: &[Event])
+    {
+        for event in events
+        {
+            self.apply_event(event);
+        }
+
+        self.predicted = self.local_player.and_then(|player_id| self.players.get(&player_id).cloned());
+        self.predicted_at = time;
+    }
+
+    pub fn predict_command(&mut self, time: f64, command: PlayerCommand)
+    {
+        if let Some(mut state) = self.predicted_state(time)
+        {
+            for action in simulation::process_command(&state, command)
+            {
+                simulation::apply_action(&mut state, action);
+            }
+
+            self.predicted = Some(state);
+            self.predicted_at = time;
+        }
+    }
+
+    pub fn predicted_state(&self, time: f64) -> Option<PlayerState>
+    {
+        self.predicted.map(|mut state|
+        {
+            for action in simulation::update(&state, &self.rules, (time - self.predicted_at) as f32)
+            {
+                simulation::apply_action(&mut state, action);
+            }
+            state
+        })
+    }
+
+    fn apply_event(&mut self, event: &Event)
+    {
+        match *event
+        {
+            Event::PlayerCreated(player_id, _, player_state) => { self.players.insert(player_id, player_state); },
+            Event::PlayerRemoved(player_id) => { self.players.remove(&player_id); },
+            Event::PlayerActed(player_id, player_action) =>
+            {
+                self.players.get_mut(&player_id).map(|player_state| simulation::apply_action(player_state, player_action));
+            },
+            Event::PlayerDamaged(target_id, _, amount) =>
+            {
+                self.players.get_mut(&target_id).map(|player_state| player_state.health = (player_state.health - amount).max(0.0));
+            },
+            Event::PlayerKilled(ref kill) =>
+            {
+                self.players.get_mut(&kill.victim).map(|player_state|
+                {
+                    player_state.alive = false;
+                    player_state.health = 0.0;
+                    player_state.movement_direction = None;
+                });
+            },
+            Event::RulesChanged(rules) => self.rules = rules,
+            Event::WorldReset => self.players.clear(),
+            Event::SessionStarted(player_id, _) => self.local_player = Some(player_id),
+            Event::Spectating(_) => self.local_player = None,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use Event;
+
+/// Bumped whenever the layout of replay files changes.
+pub const REPLAY_FORMAT_VERSION: u32 = 2;
+
+pub const REPLAY_FILE_EXTENSION: &'static str = "vpr";
+
+/// A replay file is a sequence of length prefixed records, the header first and a keyframe right
+/// after it, so every file can be played back on its own.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct ReplayHeader
+{
+    pub format_version: u32,
+    pub protocol_version: u32,
+    pub tick_rate: u32,
+    pub level: String,
+    pub game_mode: String,
+    pub started_at: i64
+}
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub enum ReplayRecord
+{
+    Header(ReplayHeader),
+    /// A snapshot of the world after the events of the tick.
+    Keyframe(u64, Vec<Event>),
+    /// The events of a tick and the match time that passed since the previous record. Ticks without
+    /// events are left out, time doesn't pass while the game is paused.
+    Tick(u64, f32, Vec<Event>)
+}
@@ -0,0 +1,175 @@
+use {PlayerState, PlayerCommand, PlayerAction, Position, Direction};
+use PlayerAction::*;
+
+#[derive(Clone, Copy, Debug, RustcEncodable, RustcDecodable)]
+pub struct Rules
+{
+    pub player_speed: f32,
+    pub player_max_health: f32,
+    pub weapon_damage: f32,
+    pub weapon_range: f32,
+    pub fire_cooldown_seconds: f32,
+    pub respawn_seconds: f32
+}
+
+impl Default for Rules
+{
+    fn default() -> Rules
+    {
+        Rules
+        {
+            player_speed: 2.0,
+            player_max_health: 100.0,
+            weapon_damage: 25.0,
+            weapon_range: 10.0,
+            fire_cooldown_seconds: 0.5,
+            respawn_seconds: 3.0
+        }
+    }
+}
+
+pub fn process_command(state: &PlayerState, command: PlayerCommand) -> Vec<PlayerAction>
+{
+    match command
+    {
+        // sessions, profiles, chat and spectating are handled by the server before commands reach the simulation
+        PlayerCommand::Join(_, _) | PlayerCommand::Rename(_) | PlayerCommand::Chat(_, _)
+        | PlayerCommand::Spectate | PlayerCommand::Follow(_) | PlayerCommand::RequestScoreboard | PlayerCommand::Pong(_) => vec![],
+        // firing needs the other players, the world resolves it with the combat module
+        PlayerCommand::Fire(_) => vec![],
+        PlayerCommand::ChangeMovementDirection(direction) =>
+        {
+            if state.alive && state.movement_direction != direction
+            {
+                vec![ChangedMovementDirection(direction)]
+            }
+            else
+            {
+                vec![]
+            }
+        }
+    }
+}
+
+pub fn update(state: &PlayerState, rules: &Rules, elapsed_seconds: f32) -> Vec<PlayerAction>
+{
+    match state.movement_direction
+    {
+        Some(direction) if state.alive => vec![Moved(move_position(state.position, direction, rules, elapsed_seconds))],
+        _ => vec![]
+    }
+}
+
+pub fn apply_action(state: &mut PlayerState, action: PlayerAction)
+{
+    match action
+    {
+        ChangedMovementDirection(new_direction) => state.movement_direction = new_direction,
+        Moved(new_position) => state.position = new_position,
+        Fired(_, _) => {}
+        Respawned(position, health) =>
+        {
+            *state = PlayerState { movement_direction: None, position: position, health: health, alive: true };
+        }
+    }
+}
+
+pub fn move_position(position: Position, direction: Direction, rules: &Rules, elapsed_seconds: f32) -> Position
+{
+    position + direction.to_vec2() * rules.player_speed * elapsed_seconds
+}
+
+#[cfg(test)]
+mod tests
+{
+    use na::Vec2;
+
+    use {PlayerState, PlayerCommand, PlayerAction, Direction, Weapon};
+    use super::*;
+
+    fn player_at(x: f32, y: f32) -> PlayerState
+    {
+        PlayerState { movement_direction: None, position: Vec2::new(x, y), health: 100.0, alive: true }
+    }
+
+    fn assert_same_state(a: &PlayerState, b: &PlayerState)
+    {
+        assert_eq!(a.movement_direction, b.movement_direction);
+        assert_eq!(a.position, b.position);
+        assert_eq!(a.health, b.health);
+        assert_eq!(a.alive, b.alive);
+    }
+
+    #[test]
+    fn changing_direction_only_acts_on_a_change()
+    {
+        let mut state = player_at(0.0, 0.0);
+        let actions = process_command(&state, PlayerCommand::ChangeMovementDirection(Some(Direction::Up)));
+        assert_eq!(actions.len(), 1);
+
+        apply_action(&mut state, actions[0]);
+        assert_eq!(state.movement_direction, Some(Direction::Up));
+        assert_eq!(process_command(&state, PlayerCommand::ChangeMovementDirection(Some(Direction::Up))).len(), 0);
+    }
+
+    #[test]
+    fn dead_players_ignore_movement()
+    {
+        let mut state = player_at(0.0, 0.0);
+        state.alive = false;
+        assert_eq!(process_command(&state, PlayerCommand::ChangeMovementDirection(Some(Direction::Left))).len(), 0);
+
+        state.movement_direction = Some(Direction::Left);
+        assert_eq!(update(&state, &Rules::default(), 1.0).len(), 0);
+    }
+
+    #[test]
+    fn commands_the_server_handles_produce_no_actions()
+    {
+        let state = player_at(0.0, 0.0);
+        assert_eq!(process_command(&state, PlayerCommand::Rename("Someone".to_string())).len(), 0);
+        assert_eq!(process_command(&state, PlayerCommand::RequestScoreboard).len(), 0);
+        assert_eq!(process_command(&state, PlayerCommand::Fire(Direction::Up)).len(), 0);
+    }
+
+    #[test]
+    fn update_moves_in_the_movement_direction()
+    {
+        let rules = Rules::default();
+        let mut state = player_at(1.0, 1.0);
+        assert_eq!(update(&state, &rules, 1.0).len(), 0);
+
+        state.movement_direction = Some(Direction::Right);
+        let actions = update(&state, &rules, 0.5);
+        assert_eq!(actions.len(), 1);
+        match actions[0]
+        {
+            PlayerAction::Moved(position) => assert_eq!(position, Vec2::new(2.0, 1.0)),
+            action => panic!("unexpected action {:?}", action)
+        }
+    }
+
+    #[test]
+    fn move_position_scales_with_speed_and_time()
+    {
+        let rules = Rules { player_speed: 4.0, .. Rules::default() };
+        let origin = Vec2::new(0.0, 0.0);
+        assert_eq!(move_position(origin, Direction::Up, &rules, 0.5), Vec2::new(0.0, 2.0));
+        assert_eq!(move_position(origin, Direction::Down, &rules, 0.25), Vec2::new(0.0, -1.0));
+        assert_eq!(move_position(origin, Direction::Left, &rules, 1.0), Vec2::new(-4.0, 0.0));
+        assert_eq!(move_position(origin, Direction::Right, &rules, 0.0), origin);
+    }
+
+    #[test]
+    fn respawning_resets_the_player()
+    {
+        let mut state = player_at(3.0, 3.0);
+        state.alive = false;
+        state.health = 0.0;
+        state.movement_direction = Some(Direction::Down);
+
+        apply_action(&mut state, PlayerAction::Fired(Weapon::Blaster, Direction::Up));
+        apply_action(&mut state, PlayerAction::Respawned(Vec2::new(0.0, 0.0), 80.0));
+        assert_same_state(&state, &PlayerState { movement_direction: None, position: Vec2::new(0.0, 0.0), health: 80.0, alive: true });
+    }
+}